@@ -11,6 +11,7 @@ fn main() {
         id.load()
             .expect("failed to search keychain")
             .expect("found no keychain items")
+            .expose()
     );
     // Alternatively we can recreate the Identifier struct.
     assert_eq!(
@@ -23,6 +24,7 @@ fn main() {
         .load()
         .expect("failed to search keychain")
         .expect("found no keychain items")
+        .expose()
     );
 
     // If we don't update the service or username, then the old id will still be
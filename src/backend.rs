@@ -0,0 +1,175 @@
+use crate::{
+    ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier, Result,
+    SearchOptions, Secret, UpdateOptions,
+};
+
+/// A store of keychain items.
+///
+/// The keychain provided by the operating system is available as
+/// [`Platform`](crate::Platform). Other stores can be used by implementing
+/// this trait and passing the implementation to [`Keychain::new`].
+///
+/// [`Keychain::new`]: crate::Keychain::new
+pub trait Backend: Send + Sync {
+    /// Adds an item to the store.
+    ///
+    /// If an item with the same identifier exists, it must be handled as
+    /// specified by the item's [`StoreMode`](crate::StoreMode).
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()>;
+
+    /// Loads the secret of an item, returning `None` if the item does not
+    /// exist.
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>>;
+
+    /// Loads the non-secret information about an item, returning `None` if
+    /// the item does not exist.
+    ///
+    /// The default implementation returns an [`ErrorKind::Unsupported`]
+    /// error.
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        let _ = identifier;
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// Updates an existing item.
+    ///
+    /// Fields not set in `options` keep their previous value. If the service,
+    /// username or class changes, the item must no longer be accessible under
+    /// the old identifier. If another item already exists under the new
+    /// identifier, the update must fail with [`ErrorKind::Duplicate`] and leave
+    /// both items unchanged.
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()>;
+
+    /// Deletes an item.
+    ///
+    /// If the item does not exist, this must fail with
+    /// [`ErrorKind::NotFound`].
+    fn delete(&self, identifier: &Identifier) -> Result<()>;
+
+    /// Returns the identifiers of items matching `options`.
+    ///
+    /// The results are filtered with [`SearchOptions::matches`] and truncated
+    /// to the limit by the caller, so backends may return a superset of the
+    /// matching items. However, backends must only return items that have all
+    /// the attributes in [`SearchOptions::attributes`].
+    ///
+    /// The default implementation returns an [`ErrorKind::Unsupported`]
+    /// error.
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        let _ = options;
+        Err(ErrorKind::Unsupported.into())
+    }
+}
+
+/// Checks that a backend meets the contract of [`Backend`].
+///
+/// The backend must be empty, and support searching and the internet class.
+#[cfg(all(
+    test,
+    any(
+        feature = "memory",
+        feature = "file",
+        target_os = "android",
+        all(target_os = "linux", feature = "keyutils"),
+    )
+))]
+pub(crate) fn check_backend(backend: &dyn Backend) {
+    use crate::{Class, StoreMode};
+
+    let id = |service, username, class| Identifier {
+        service,
+        username,
+        class,
+    };
+    let secret = |id: &Identifier| backend.load(id).unwrap().map(|s| s.expose().clone());
+    let kind = |result: Result<()>| result.unwrap_err().kind();
+
+    // Store modes.
+    let a = id("service", Some("a"), Class::Generic);
+    let item = |secret| KeychainItemBuilder::new("service", secret).username("a");
+    backend
+        .store(&item("first").mode(StoreMode::CreateNew))
+        .unwrap();
+    let error = kind(backend.store(&item("x").mode(StoreMode::CreateNew)));
+    assert_eq!(error, ErrorKind::Duplicate);
+    backend.store(&item("second")).unwrap();
+    backend
+        .store(&item("third").mode(StoreMode::Replace))
+        .unwrap();
+    assert_eq!(secret(&a).unwrap(), b"third");
+    let error =
+        kind(backend.store(&KeychainItemBuilder::new("other", "x").mode(StoreMode::Replace)));
+    assert_eq!(error, ErrorKind::NotFound);
+    assert!(secret(&id("other", None, Class::Generic)).is_none());
+
+    // Items without a username or of another class are distinct.
+    let none = id("service", None, Class::Generic);
+    let internet = id("service", Some("a"), Class::Internet);
+    backend
+        .store(&KeychainItemBuilder::new("service", "none").mode(StoreMode::CreateNew))
+        .unwrap();
+    backend
+        .store(
+            &item("internet")
+                .class(Class::Internet)
+                .mode(StoreMode::CreateNew),
+        )
+        .unwrap();
+    assert_eq!(secret(&none).unwrap(), b"none");
+    assert_eq!(secret(&internet).unwrap(), b"internet");
+    assert_eq!(secret(&a).unwrap(), b"third");
+
+    // Renaming onto an existing item fails and leaves both unchanged.
+    let b = id("service", Some("b"), Class::Generic);
+    backend
+        .store(&KeychainItemBuilder::new("service", "b").username("b"))
+        .unwrap();
+    let error = kind(backend.update(&a, &UpdateOptions::new().username("b").secret("x")));
+    assert_eq!(error, ErrorKind::Duplicate);
+    let error = kind(backend.update(&a, &UpdateOptions::new().class(Class::Internet)));
+    assert_eq!(error, ErrorKind::Duplicate);
+    assert_eq!(secret(&a).unwrap(), b"third");
+    assert_eq!(secret(&b).unwrap(), b"b");
+
+    // Renaming moves the item, and updating it in place isn't a conflict.
+    let c = id("service", Some("c"), Class::Generic);
+    backend
+        .update(&a, &UpdateOptions::new().username("c").secret("c"))
+        .unwrap();
+    assert!(secret(&a).is_none());
+    assert_eq!(secret(&c).unwrap(), b"c");
+    backend
+        .update(&c, &UpdateOptions::new().username("c"))
+        .unwrap();
+    let error = kind(backend.update(&a, &UpdateOptions::new()));
+    assert_eq!(error, ErrorKind::NotFound);
+
+    // Searches may return more items than match, which callers filter out.
+    let search = |options: SearchOptions| {
+        let mut found = backend.search(&options).unwrap();
+        found.retain(|id| options.matches(&id.as_ref()));
+        found.sort();
+        found
+    };
+    assert_eq!(search(SearchOptions::new().service("other")), []);
+    assert_eq!(
+        search(SearchOptions::new().service("service")),
+        [none, internet, b, c].map(|id| id.to_owned())
+    );
+    assert_eq!(
+        search(SearchOptions::new().username_prefix("c")),
+        [c.to_owned()]
+    );
+    assert_eq!(
+        search(SearchOptions::new().class(Class::Internet)),
+        [internet.to_owned()]
+    );
+
+    // Deleting a missing item fails.
+    for id in [none, b, c, internet] {
+        backend.delete(&id).unwrap();
+        assert!(secret(&id).is_none());
+        assert_eq!(kind(backend.delete(&id)), ErrorKind::NotFound);
+    }
+    assert_eq!(search(SearchOptions::new()), []);
+}
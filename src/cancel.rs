@@ -0,0 +1,127 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancellationToken>> = const { RefCell::new(None) };
+}
+
+/// A handle to cancel an asynchronous keychain operation.
+///
+/// Each [`Task`](crate::Task) has its own token, available through
+/// [`Task::cancellation_token`](crate::Task::cancellation_token). Cancelling
+/// the token resolves the task with an [`ErrorKind::Cancelled`] error.
+///
+/// On Linux, the token is forwarded to the Secret Service backend so that
+/// unlock prompts are dismissed, and with libsecret, pending D-Bus calls are
+/// aborted. A `FileKeychain` stops waiting for its lock, whether or not it has
+/// a lock timeout, and fails before it modifies an item. Other platforms can't interrupt a keychain operation, so
+/// it runs to completion on its worker thread and the result is discarded.
+///
+/// Cloning a `CancellationToken` is cheap; clones refer to the same
+/// operation.
+///
+/// [`ErrorKind::Cancelled`]: crate::ErrorKind::Cancelled
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    callbacks: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the operation.
+    ///
+    /// Cancelling a token more than once has no effect.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let callbacks = std::mem::take(
+            &mut *self
+                .inner
+                .callbacks
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Returns whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Runs `callback` when the token is cancelled, or immediately if it
+    /// already has been.
+    pub(crate) fn on_cancel<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut callbacks = self
+            .inner
+            .callbacks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Checked while holding the lock so that a concurrent `cancel` either
+        // sees the callback or is seen here.
+        if self.is_cancelled() {
+            drop(callbacks);
+            callback();
+        } else {
+            callbacks.push(Box::new(callback));
+        }
+    }
+
+    /// Runs `f` with `self` as the token of the current thread.
+    pub(crate) fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let result = f();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+
+    /// Returns the token of the operation running on the current thread, if
+    /// any.
+    ///
+    /// Backends that can abort an operation use this to forward cancellation
+    /// to the platform API.
+    #[cfg_attr(
+        not(any(
+            feature = "file",
+            target_os = "android",
+            all(
+                target_os = "linux",
+                any(feature = "libsecret", feature = "secret-service")
+            )
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
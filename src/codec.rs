@@ -0,0 +1,115 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Result;
+
+/// A format used to store typed values as secrets.
+///
+/// A codec converts a value to the bytes of a secret when it is stored with
+/// [`KeychainItemBuilder::store_encoded`], and back when it is loaded with
+/// [`Identifier::load_decoded`]. The crate provides `Json` with the `json`
+/// feature; other formats such as CBOR or MessagePack can be added by
+/// implementing this trait:
+///
+/// ```ignore
+/// use robius_keychain::{Codec, Error, ErrorKind, Result};
+/// use serde::{de::DeserializeOwned, Serialize};
+///
+/// struct Cbor;
+///
+/// impl Codec for Cbor {
+///     fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+///         let mut bytes = Vec::new();
+///         ciborium::into_writer(value, &mut bytes)
+///             .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+///         Ok(bytes)
+///     }
+///
+///     fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+///         ciborium::from_reader(bytes).map_err(|e| Error::new(ErrorKind::Decode, e))
+///     }
+/// }
+/// ```
+///
+/// [`KeychainItemBuilder::store_encoded`]: crate::KeychainItemBuilder::store_encoded
+/// [`Identifier::load_decoded`]: crate::Identifier::load_decoded
+pub trait Codec {
+    /// Encodes a value to the bytes of a secret.
+    ///
+    /// Values that can't be represented in the format should be reported as
+    /// an [`ErrorKind::InvalidData`](crate::ErrorKind::InvalidData) error.
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized;
+
+    /// Decodes a value from the bytes of a secret.
+    ///
+    /// Malformed secrets should be reported as an
+    /// [`ErrorKind::Decode`](crate::ErrorKind::Decode) error.
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned;
+}
+
+/// A [`Codec`] storing values as JSON.
+#[cfg(feature = "json")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        serde_json::to_vec(value).map_err(|e| crate::Error::new(crate::ErrorKind::InvalidData, e))
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(|e| crate::Error::new(crate::ErrorKind::Decode, e))
+    }
+}
+
+#[cfg(all(test, feature = "json", feature = "memory"))]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{ErrorKind, Keychain, KeychainItemBuilder, MemoryKeychain};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Token {
+        access: String,
+        expires_in: u32,
+        scopes: Vec<String>,
+    }
+
+    #[test]
+    fn roundtrip() {
+        let keychain = Keychain::new(MemoryKeychain::new());
+        let token = Token {
+            access: "abc".to_owned(),
+            expires_in: 3600,
+            scopes: vec!["read".to_owned(), "write".to_owned()],
+        };
+        let id = keychain
+            .store_encoded(KeychainItemBuilder::new("service", ""), &Json, &token)
+            .unwrap();
+        assert_eq!(keychain.load_decoded(&id, &Json).unwrap(), Some(token));
+
+        keychain.delete(&id).unwrap();
+        assert_eq!(keychain.load_decoded::<_, Token>(&id, &Json).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_secret() {
+        let keychain = Keychain::new(MemoryKeychain::new());
+        let id = keychain
+            .store(KeychainItemBuilder::new("service", "not json"))
+            .unwrap();
+        let error = keychain.load_decoded::<_, Token>(&id, &Json).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Decode);
+    }
+}
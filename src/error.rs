@@ -1,85 +1,147 @@
-use std::{error, fmt};
+use std::{error, fmt, io};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-cfg_if::cfg_if! {
-    if #[cfg(target_os = "android")] {
-        #[derive(Debug)]
-        pub struct Error(pub(crate) ErrorVariant);
+/// An error returned by a keychain operation.
+///
+/// The native error reported by the backend, if any, is available through
+/// [`source`](error::Error::source), and its message is included when the
+/// error is displayed.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+}
 
-        #[derive(Debug)]
-        pub(crate) enum ErrorVariant {
-            Io(std::io::Error),
-            Utf8(std::string::FromUtf8Error),
-            Directories,
-        }
+/// The category of an [`Error`].
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The item does not exist.
+    NotFound,
+    /// An item with the same identifier already exists.
+    Duplicate,
+    /// The application is not allowed to access the item or keychain.
+    AccessDenied,
+    /// The files of the keychain can be accessed by other users, so it refuses
+    /// to use them.
+    InsecurePermissions,
+    /// The keychain is locked, or another process holds the lock of a
+    /// `FileKeychain` for too long.
+    Locked,
+    /// The user cancelled the operation, e.g. by dismissing an unlock prompt.
+    Cancelled,
+    /// The operation did not complete within the timeout set with
+    /// [`Keychain::with_timeout`](crate::Keychain::with_timeout).
+    ///
+    /// The operation is cancelled, but backends that can't interrupt it keep
+    /// running it in the background, so a write may still have happened.
+    TimedOut,
+    /// The keychain could not be reached, e.g. because the Secret Service
+    /// daemon is not running.
+    Unavailable,
+    /// The item or the supplied data is malformed.
+    InvalidData,
+    /// The secret could not be decoded by a `Codec`, e.g. because it was
+    /// stored in a different format.
+    Decode,
+    /// The backend does not support the operation.
+    Unsupported,
+    /// An error that does not fit any other kind.
+    Other,
+}
 
-        impl From<std::io::Error> for Error {
-            fn from(error: std::io::Error) -> Self {
-                Self(ErrorVariant::Io(error))
-            }
+impl Error {
+    /// Creates a new error from a kind and an underlying error.
+    ///
+    /// This is intended for [`Backend`](crate::Backend) implementations.
+    pub fn new<E>(kind: ErrorKind, error: E) -> Self
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        Self {
+            kind,
+            source: Some(error.into()),
         }
+    }
 
-        impl From<std::string::FromUtf8Error> for Error {
-            fn from(error: std::string::FromUtf8Error) -> Self {
-                Self(ErrorVariant::Utf8(error))
-            }
-        }
-    } else if #[cfg(target_vendor = "apple")] {
-        #[derive(Debug)]
-        pub struct Error(pub(crate) security_framework::base::Error);
-    } else if #[cfg(target_os = "linux")] {
-        #[derive(Debug)]
-        pub struct Error(pub(crate) glib::error::Error);
-    } else if #[cfg(target_os = "windows")] {
-        #[derive(Debug)]
-        pub struct Error(pub(crate) windows_result::Error);
-    } else {
-        #[derive(Debug)]
-        pub struct Error(());
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, source: None }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        let kind = match error.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::AlreadyExists => ErrorKind::Duplicate,
+            io::ErrorKind::PermissionDenied => ErrorKind::AccessDenied,
+            io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+            io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+            io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, error)
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        cfg_if::cfg_if! {
-            if #[cfg(target_os = "android")] {
-                match self.0 {
-                    ErrorVariant::Io(ref e) => write!(f, "{e}"),
-                    ErrorVariant::Utf8(ref e) => write!(f, "{e}"),
-                    ErrorVariant::Directories => write!(f, "todo"),
-                }
-            } else if #[cfg(target_vendor = "apple")] {
-                write!(f, "{}", self.0)
-            } else if #[cfg(target_os = "linux")] {
-                write!(f, "{}", self.0)
-            } else if #[cfg(target_os = "windows")] {
-                write!(f, "{}", self.0)
-            } else {
-                write!(f, "error")
-            }
+        match self.source {
+            Some(ref e) => write!(f, "{}: {e}", self.kind),
+            None => fmt::Display::fmt(&self.kind, f),
         }
     }
 }
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        cfg_if::cfg_if! {
-            if #[cfg(target_os = "android")] {
-                match self.0 {
-                    ErrorVariant::Io(ref e) => Some(e),
-                    ErrorVariant::Utf8(ref e) => Some(e),
-                    ErrorVariant::Directories => None,
-                }
-            } else if #[cfg(target_vendor = "apple")] {
-                Some(&self.0)
-            } else if #[cfg(target_os = "linux")] {
-                Some(&self.0)
-            } else if #[cfg(target_os = "windows")] {
-                Some(&self.0)
-            } else {
-                None
-            }
+        match self.source {
+            Some(ref e) => Some(&**e),
+            None => None,
         }
     }
 }
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotFound => "item not found",
+            Self::Duplicate => "item already exists",
+            Self::AccessDenied => "access denied",
+            Self::InsecurePermissions => "insecure file permissions",
+            Self::Locked => "keychain is locked",
+            Self::Cancelled => "operation cancelled",
+            Self::TimedOut => "operation timed out",
+            Self::Unavailable => "keychain unavailable",
+            Self::InvalidData => "invalid data",
+            Self::Decode => "failed to decode secret",
+            Self::Unsupported => "operation not supported",
+            Self::Other => "keychain error",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_includes_source() {
+        let error = Error::new(ErrorKind::InvalidData, "invalid namespace 'a/b'");
+        assert_eq!(error.to_string(), "invalid data: invalid namespace 'a/b'");
+        assert_eq!(
+            Error::from(ErrorKind::NotFound).to_string(),
+            "item not found"
+        );
+    }
+}
@@ -0,0 +1,1339 @@
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, TryLockError},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Backend, CancellationToken, Class, Error, ErrorKind, Identifier, ItemMetadata,
+    KeychainItemBuilder, OwnedIdentifier, Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
+
+/// The subdirectory holding the metadata sidecar files.
+const METADATA_DIR: &str = ".metadata";
+/// The start of an item file, followed by the length-prefixed identifier, the
+/// nonce and the ciphertext.
+const MAGIC: &[u8] = b"RKC\x01";
+const NONCE_LEN: usize = 24;
+/// The prefix of temporary files, which are moved into place once written.
+const TEMP_PREFIX: &str = ".tmp-";
+/// The file locked by writers, so that writers in other processes wait for
+/// each other.
+const LOCK_FILE: &str = ".lock";
+/// The namespace used if none is set with [`FileKeychainBuilder::namespace`].
+const DEFAULT_NAMESPACE: &str = "robius-keychain";
+
+/// A keychain storing each item in an encrypted file.
+///
+/// This is the [`Platform`](crate::Platform) keychain on Android, and is
+/// available on other platforms with the `file` feature, e.g. for machines
+/// without a platform keychain.
+///
+/// Each item is stored in a file named after the SHA-256 hash of its class,
+/// service and username, so any identifier maps to a distinct file name that
+/// is safe on every filesystem. The identifier is also stored in the file to
+/// list items in [`search`](Backend::search).
+///
+/// Secrets are encrypted with XChaCha20-Poly1305 using the master key of a
+/// [`KeyProvider`]. The identifier of the item is authenticated along with the
+/// secret, so a file copied or renamed to another item fails to load with an
+/// [`ErrorKind::InvalidData`] error. The identifier, label, comment and
+/// attributes are not encrypted.
+///
+/// Files are written to a temporary file and synced before being moved into
+/// place, so a crash never leaves a partially written item behind. Temporary
+/// files left by a crash are removed the first time an item is written.
+///
+/// Operations that modify items hold an advisory lock on the directory, so
+/// concurrent writers, including those in other processes, are serialized.
+/// See [`LockWait`] for how long they wait for each other.
+///
+/// On Unix, the directory is created with mode `0700` and files with mode
+/// `0600`. Operations fail with an [`ErrorKind::InsecurePermissions`] error if
+/// the directory is a symlink, is owned by another user or can be accessed by
+/// other users.
+///
+/// A `FileKeychain` is created with [`FileKeychain::builder`], or with
+/// [`FileKeychain::new`] to choose the directory and key provider directly:
+///
+/// ```no_run
+/// use robius_keychain::{FileKeychain, KeyFile, Keychain};
+///
+/// let keychain = FileKeychain::new(
+///     "/var/lib/app/keychain",
+///     KeyFile::new("/etc/app/keychain.key"),
+/// );
+/// Keychain::set_default(Keychain::new(keychain));
+/// ```
+pub struct FileKeychain {
+    dir: PathBuf,
+    key_provider: Box<dyn KeyProvider>,
+    lock_wait: LockWait,
+    recovered: Once,
+}
+
+/// How long to wait for another writer to release the lock of a
+/// [`FileKeychain`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LockWait {
+    /// Wait until the lock is released or the operation is cancelled.
+    Block,
+    /// Wait until the lock is released or the timeout expires, after which the
+    /// operation fails with an [`ErrorKind::Locked`] error.
+    ///
+    /// A timeout of zero fails immediately if the lock is held.
+    Timeout(Duration),
+}
+
+impl Default for LockWait {
+    /// Waits for up to 10 seconds.
+    fn default() -> Self {
+        Self::Timeout(Duration::from_secs(10))
+    }
+}
+
+/// Metadata stored in a sidecar file next to the secret.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Metadata {
+    /// When the item was first stored. The creation time of the item's file
+    /// can't be used, as every write replaces the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<SystemTime>,
+    /// When the item was last changed. The modification time of the item's
+    /// file isn't changed by updates that only change the metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default)]
+    attributes: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    fn is_empty(&self) -> bool {
+        self.created.is_none()
+            && self.modified.is_none()
+            && self.label.is_none()
+            && self.comment.is_none()
+            && self.attributes.is_empty()
+    }
+}
+
+impl FileKeychain {
+    /// Creates a new `FileKeychain` storing items in `dir`, encrypted with the
+    /// key of `key_provider`.
+    ///
+    /// The directory is created when the first item is stored.
+    pub fn new<K>(dir: impl Into<PathBuf>, key_provider: K) -> Self
+    where
+        K: KeyProvider + 'static,
+    {
+        Self {
+            dir: dir.into(),
+            key_provider: Box::new(key_provider),
+            lock_wait: LockWait::default(),
+            recovered: Once::new(),
+        }
+    }
+
+    /// Sets how long to wait for another writer to release the lock.
+    ///
+    /// Defaults to [`LockWait::default`].
+    pub fn with_lock_wait(mut self, lock_wait: LockWait) -> Self {
+        self.lock_wait = lock_wait;
+        self
+    }
+
+    /// Returns a builder to configure where the keychain is stored.
+    pub fn builder() -> FileKeychainBuilder {
+        FileKeychainBuilder::default()
+    }
+
+    /// Returns the directory the items are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Checks the directory before an operation.
+    fn check(&self) -> Result<()> {
+        check_dir(&self.dir)
+    }
+
+    /// Locks the directory for writing, returning the lock file. The lock is
+    /// released when the file is dropped.
+    ///
+    /// The temporary files left by a crash are removed the first time.
+    fn lock(&self) -> Result<File> {
+        self.check()?;
+
+        let mut options = File::options();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(self.dir.join(LOCK_FILE))?;
+
+        // The lock is polled rather than waited for, so that a cancelled
+        // operation stops waiting.
+        let deadline = match self.lock_wait {
+            LockWait::Block => None,
+            LockWait::Timeout(timeout) => Some(Instant::now() + timeout),
+        };
+        let mut delay = Duration::from_millis(1);
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(error)) => return Err(Error::from(error)),
+            }
+            check_cancelled()?;
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(Error::new(
+                    ErrorKind::Locked,
+                    "the keychain is locked by another writer",
+                ));
+            }
+            let remaining = deadline.map_or(delay, |deadline| deadline - now);
+            thread::sleep(delay.min(remaining));
+            delay = (delay * 2).min(Duration::from_millis(50));
+        }
+
+        // Temporary files are only removed while holding the lock, as they may
+        // belong to another writer otherwise. This is best effort, as leftover
+        // files don't affect the items.
+        self.recovered.call_once(|| {
+            let _ = remove_temp_files(&self.dir);
+            let _ = remove_temp_files(&self.dir.join(METADATA_DIR));
+        });
+        // The operation may have timed out while waiting for the lock.
+        check_cancelled()?;
+        Ok(file)
+    }
+
+    fn file(&self, identifier: &Identifier) -> PathBuf {
+        self.dir.join(file_name(identifier))
+    }
+
+    fn metadata_file(&self, identifier: &Identifier) -> PathBuf {
+        self.dir.join(METADATA_DIR).join(file_name(identifier))
+    }
+
+    fn read_metadata(&self, identifier: &Identifier) -> Result<Metadata> {
+        match fs::read(self.metadata_file(identifier)) {
+            Ok(bytes) => {
+                serde_json::from_slice(&bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            }
+            Err(error) => match error.kind() {
+                io::ErrorKind::NotFound => Ok(Metadata::default()),
+                _ => Err(Error::from(error)),
+            },
+        }
+    }
+
+    /// Writes the metadata of an item, removing the sidecar if there is
+    /// nothing to store.
+    fn write_metadata(&self, identifier: &Identifier, metadata: &Metadata) -> Result<()> {
+        if metadata.is_empty() {
+            return self.remove_metadata(identifier);
+        }
+
+        let bytes = serde_json::to_vec(metadata).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        create_dir(&self.dir.join(METADATA_DIR))?;
+        write_atomic(&self.metadata_file(identifier), &bytes, StoreMode::Upsert)?;
+        Ok(())
+    }
+
+    fn remove_metadata(&self, identifier: &Identifier) -> Result<()> {
+        match fs::remove_file(self.metadata_file(identifier)) {
+            Ok(()) => Ok(()),
+            Err(error) => match error.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(Error::from(error)),
+            },
+        }
+    }
+
+    fn cipher(&self) -> Result<XChaCha20Poly1305> {
+        let key = self.key_provider.key()?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(key.expose())))
+    }
+
+    /// Encrypts a secret into the contents of the item's file.
+    fn encrypt(&self, identifier: &Identifier, secret: &[u8]) -> Result<Vec<u8>> {
+        let mut contents = MAGIC.to_vec();
+        push_field(&mut contents, &encode_identifier(identifier));
+
+        // Everything before the nonce is authenticated.
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()?
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: secret,
+                    aad: &contents,
+                },
+            )
+            .map_err(|_| Error::new(ErrorKind::Other, "failed to encrypt item"))?;
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        Ok(contents)
+    }
+
+    /// Decrypts the contents of the item's file.
+    fn decrypt(&self, identifier: &Identifier, contents: &[u8]) -> Result<Secret<Vec<u8>>> {
+        let item = ItemFile::parse(contents).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "item is not an encrypted keychain item",
+            )
+        })?;
+        if item.header != encode_identifier(identifier) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "item file belongs to another item",
+            ));
+        }
+        self.cipher()?
+            .decrypt(
+                XNonce::from_slice(item.nonce),
+                Payload {
+                    msg: item.ciphertext,
+                    aad: item.associated_data,
+                },
+            )
+            .map(Secret::new)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "failed to decrypt item"))
+    }
+}
+
+impl Backend for FileKeychain {
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()> {
+        create_dir(&self.dir)?;
+        let _lock = self.lock()?;
+
+        let identifier = Identifier {
+            service: item.service,
+            class: item.class,
+            username: item.username,
+        };
+        // A replaced item keeps its creation time.
+        let created = match fs::metadata(self.file(&identifier)) {
+            Ok(file_metadata) => self
+                .read_metadata(&identifier)?
+                .created
+                .or_else(|| file_metadata.created().ok()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(Error::from(error)),
+        };
+        let contents = self.encrypt(&identifier, item.secret)?;
+        check_cancelled()?;
+        write_atomic(&self.file(&identifier), &contents, item.mode)?;
+
+        let now = SystemTime::now();
+        let metadata = Metadata {
+            created: Some(created.unwrap_or(now)),
+            modified: Some(now),
+            label: item.label.map(ToOwned::to_owned),
+            comment: item.comment.map(ToOwned::to_owned),
+            attributes: item
+                .attributes
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        };
+        self.write_metadata(&identifier, &metadata)
+    }
+
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        self.check()?;
+        match fs::read(self.file(identifier)) {
+            Ok(contents) => self.decrypt(identifier, &contents).map(Some),
+            Err(error) => match error.kind() {
+                io::ErrorKind::NotFound => Ok(None),
+                _ => Err(Error::from(error)),
+            },
+        }
+    }
+
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        self.check()?;
+        let file_metadata = match fs::metadata(self.file(identifier)) {
+            Ok(file_metadata) => file_metadata,
+            Err(error) => {
+                return match error.kind() {
+                    io::ErrorKind::NotFound => Ok(None),
+                    _ => Err(Error::from(error)),
+                }
+            }
+        };
+
+        let Metadata {
+            created,
+            modified,
+            label,
+            comment,
+            attributes,
+        } = self.read_metadata(identifier)?;
+        let mut metadata = ItemMetadata::new();
+        metadata.class = identifier.class;
+        // Items stored before these times were recorded fall back to the times
+        // of their file.
+        metadata.created = created.or_else(|| file_metadata.created().ok());
+        metadata.modified = modified.or_else(|| file_metadata.modified().ok());
+        metadata.label = label;
+        metadata.comment = comment;
+        metadata.attributes = attributes;
+        Ok(Some(metadata))
+    }
+
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        let _lock = self.lock()?;
+        let new_identifier = Identifier {
+            service: options.service.unwrap_or(identifier.service),
+            class: options.class.unwrap_or(identifier.class),
+            username: match options.username {
+                Some(username) => Some(username),
+                None => identifier.username,
+            },
+        };
+
+        let old_path = self.file(identifier);
+        let new_path = self.file(&new_identifier);
+        // Fails with a `NotFound` error if the item doesn't exist.
+        let file_metadata = fs::metadata(&old_path)?;
+
+        let mut metadata = self.read_metadata(identifier)?;
+        metadata.created = metadata.created.or_else(|| file_metadata.created().ok());
+        metadata.modified = Some(SystemTime::now());
+        if let Some(label) = options.label {
+            metadata.label = Some(label.to_owned());
+        }
+        if let Some(comment) = options.comment {
+            metadata.comment = Some(comment.to_owned());
+        }
+        metadata.attributes.extend(
+            options
+                .attributes
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned())),
+        );
+
+        if old_path != new_path {
+            // The secret is encrypted again, as the identifier is authenticated.
+            // The new item is written before the old one is removed, so a crash
+            // in between leaves both items intact. Writing it fails with a
+            // `Duplicate` error if another item has the new identifier.
+            let loaded;
+            let secret = match options.secret {
+                Some(secret) => secret,
+                None => {
+                    loaded = self.load(identifier)?.ok_or(ErrorKind::NotFound)?;
+                    loaded.expose().as_slice()
+                }
+            };
+            let contents = self.encrypt(&new_identifier, secret)?;
+            check_cancelled()?;
+            write_atomic(&new_path, &contents, StoreMode::CreateNew)?;
+            fs::remove_file(old_path)?;
+            self.remove_metadata(identifier)?;
+        } else if let Some(secret) = options.secret {
+            let contents = self.encrypt(&new_identifier, secret)?;
+            check_cancelled()?;
+            write_atomic(&new_path, &contents, StoreMode::Upsert)?;
+        }
+
+        self.write_metadata(&new_identifier, &metadata)
+    }
+
+    fn delete(&self, identifier: &Identifier) -> Result<()> {
+        let _lock = self.lock()?;
+        fs::remove_file(self.file(identifier))?;
+        self.remove_metadata(identifier)
+    }
+
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        self.check()?;
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                return match error.kind() {
+                    io::ErrorKind::NotFound => Ok(Vec::new()),
+                    _ => Err(Error::from(error)),
+                }
+            }
+        };
+
+        let mut identifiers = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            // Skips temporary files and the lock file.
+            if entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with('.'))
+            {
+                continue;
+            }
+            // Other files that aren't items are skipped too.
+            let contents = fs::read(entry.path())?;
+            let Some(identifier) =
+                ItemFile::parse(&contents).and_then(|item| decode_identifier(item.header))
+            else {
+                continue;
+            };
+            if !options.attributes.is_empty() {
+                let metadata = self.read_metadata(&identifier.as_ref())?;
+                if !options.attributes.iter().all(|&(key, value)| {
+                    metadata.attributes.get(key).map(AsRef::as_ref) == Some(value)
+                }) {
+                    continue;
+                }
+            }
+            identifiers.push(identifier);
+        }
+        Ok(identifiers)
+    }
+}
+
+impl fmt::Debug for FileKeychain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileKeychain")
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builder to configure a [`FileKeychain`].
+///
+/// Items are stored in the `namespace` subdirectory of the root directory,
+/// and, unless another key provider is set, the master key is kept in a
+/// [`KeyFile`] named `namespace.key` in the root directory. Keychains with
+/// different namespaces are independent, even if they share a root.
+///
+/// The root defaults to the app's data directory on Android, and to the
+/// user's data directory on other platforms, e.g. `~/.local/share` on Linux.
+///
+/// ```no_run
+/// use robius_keychain::{FileKeychain, Keychain};
+///
+/// let keychain = FileKeychain::builder()
+///     .namespace("my-app")
+///     .fallback_root("/var/lib/my-app")
+///     .build()
+///     .expect("failed to determine keychain directory");
+/// Keychain::set_default(Keychain::new(keychain));
+/// ```
+#[derive(Default)]
+pub struct FileKeychainBuilder {
+    root: Option<PathBuf>,
+    fallback_roots: Vec<PathBuf>,
+    namespace: Option<String>,
+    key_provider: Option<Box<dyn KeyProvider>>,
+    lock_wait: Option<LockWait>,
+}
+
+impl FileKeychainBuilder {
+    /// Sets the root directory, instead of the default data directory.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Adds a root directory to use if no root is set and the default data
+    /// directory can't be determined, e.g. in a container without a home
+    /// directory.
+    ///
+    /// Fallbacks are tried in the order they are added, and the first one
+    /// whose parent directory exists is used.
+    pub fn fallback_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.fallback_roots.push(root.into());
+        self
+    }
+
+    /// Sets the namespace, which is the name of the directory holding the
+    /// items.
+    ///
+    /// Defaults to `robius-keychain`. The namespace must be a valid file
+    /// name that doesn't start with a `.`.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets the provider of the master key, instead of a [`KeyFile`] in the
+    /// root directory.
+    pub fn key_provider<K>(mut self, key_provider: K) -> Self
+    where
+        K: KeyProvider + 'static,
+    {
+        self.key_provider = Some(Box::new(key_provider));
+        self
+    }
+
+    /// Sets how long to wait for another writer to release the lock.
+    ///
+    /// See [`FileKeychain::with_lock_wait`].
+    pub fn lock_wait(mut self, lock_wait: LockWait) -> Self {
+        self.lock_wait = Some(lock_wait);
+        self
+    }
+
+    /// Creates the keychain.
+    ///
+    /// Returns an [`ErrorKind::InvalidData`] error if the namespace is not a
+    /// valid file name, and an [`ErrorKind::Unavailable`] error if no root
+    /// directory is set and none could be determined. The directories are only
+    /// created when the first item is stored.
+    pub fn build(self) -> Result<FileKeychain> {
+        let namespace = self.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+        if namespace.is_empty()
+            || namespace.starts_with('.')
+            || namespace.contains(['/', '\\', '\0'])
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid keychain namespace {namespace:?}"),
+            ));
+        }
+
+        let root = match self.root {
+            Some(root) => root,
+            None => default_root()
+                .or_else(|| {
+                    self.fallback_roots
+                        .into_iter()
+                        .find(|root| root.parent().is_none_or(Path::is_dir))
+                })
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Unavailable,
+                        "could not determine a data directory for the keychain; set a root \
+                         directory with `FileKeychainBuilder::root`",
+                    )
+                })?,
+        };
+
+        let key_provider = self
+            .key_provider
+            .unwrap_or_else(|| Box::new(KeyFile::new(root.join(format!("{namespace}.key")))));
+        Ok(FileKeychain {
+            dir: root.join(namespace),
+            key_provider,
+            lock_wait: self.lock_wait.unwrap_or_default(),
+            recovered: Once::new(),
+        })
+    }
+}
+
+impl fmt::Debug for FileKeychainBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileKeychainBuilder")
+            .field("root", &self.root)
+            .field("fallback_roots", &self.fallback_roots)
+            .field("namespace", &self.namespace)
+            .field("lock_wait", &self.lock_wait)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the directory apps store their data in.
+#[cfg(target_os = "android")]
+fn default_root() -> Option<PathBuf> {
+    robius_directories::ProjectDirs::from("", "", "")
+        .map(|project_dirs| project_dirs.data_dir().to_owned())
+}
+
+/// Returns the user's data directory.
+#[cfg(not(target_os = "android"))]
+fn default_root() -> Option<PathBuf> {
+    // Relative paths are ignored, as they depend on the working directory.
+    let var = |name| {
+        std::env::var_os(name)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    if cfg!(windows) {
+        var("LOCALAPPDATA")
+    } else if cfg!(target_vendor = "apple") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    }
+}
+
+/// A source of the master key used by [`FileKeychain`] to encrypt items.
+///
+/// Implementations can keep the key in a hardware-backed store, such as the
+/// Android Keystore, or derive it from a passphrase.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the 256-bit master key.
+    ///
+    /// The key must not change, as items encrypted with another key can't be
+    /// decrypted.
+    fn key(&self) -> Result<Secret<[u8; 32]>>;
+}
+
+/// A [`KeyProvider`] holding the master key in memory.
+#[derive(Clone, Debug)]
+pub struct SoftwareKeyProvider {
+    key: Secret<[u8; 32]>,
+}
+
+impl SoftwareKeyProvider {
+    /// Creates a new `SoftwareKeyProvider` with the given key.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key: Secret::new(key),
+        }
+    }
+
+    /// Creates a new `SoftwareKeyProvider` with a random key.
+    ///
+    /// The key is lost when the provider is dropped, so this is mostly useful
+    /// for tests.
+    pub fn generate() -> Self {
+        Self {
+            key: generate_key(),
+        }
+    }
+}
+
+impl KeyProvider for SoftwareKeyProvider {
+    fn key(&self) -> Result<Secret<[u8; 32]>> {
+        Ok(self.key.clone())
+    }
+}
+
+/// A [`KeyProvider`] keeping the master key in a file.
+///
+/// The file is created with a random key when the key is first needed. The
+/// items are only as safe as the key file, so it should be kept out of places
+/// the items are copied to, such as backups.
+///
+/// This is the key provider of the [`Platform`](crate::Platform) keychain on
+/// Android.
+#[derive(Clone, Debug)]
+pub struct KeyFile {
+    path: PathBuf,
+}
+
+impl KeyFile {
+    /// Creates a new `KeyFile` reading the key from `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn read(&self) -> Result<Secret<[u8; 32]>> {
+        let mut file = File::open(&self.path)?;
+        // Waits for the writer that created the file to finish writing the key.
+        file.lock_shared()?;
+        let metadata = file.metadata()?;
+        #[cfg(unix)]
+        if metadata.mode() & 0o077 != 0 {
+            return Err(Error::new(
+                ErrorKind::InsecurePermissions,
+                format!("{} can be accessed by other users", self.path.display()),
+            ));
+        }
+        // The writer crashed before writing the key, so it is created again.
+        if metadata.len() == 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+
+        let mut key = Secret::new([0; 32]);
+        let complete = match file.read_exact(key.expose_mut()) {
+            Ok(()) => file.read(&mut [0])? == 0,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(error) => return Err(Error::from(error)),
+        };
+        if !complete {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "key file does not contain a 256-bit key",
+            ));
+        }
+        Ok(key)
+    }
+}
+
+impl KeyProvider for KeyFile {
+    fn key(&self) -> Result<Secret<[u8; 32]>> {
+        match self.read() {
+            Ok(key) => return Ok(key),
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        create_dir(dir)?;
+        // The key is written in place while holding a lock on the file, as
+        // hard links and exclusive renames aren't supported everywhere, e.g.
+        // for Android apps or on FAT file systems.
+        let mut options = File::options();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&self.path)?;
+        file.lock()?;
+        // Another thread or process created the key first.
+        if file.metadata()?.len() != 0 {
+            drop(file);
+            return self.read();
+        }
+
+        let key = generate_key();
+        file.write_all(key.expose())?;
+        file.sync_all()?;
+        sync_dir(dir)?;
+        Ok(key)
+    }
+}
+
+/// Fails with an [`ErrorKind::Cancelled`] error if the operation running on the
+/// current thread was cancelled, e.g. because its timeout expired.
+///
+/// This is checked before an item is modified, so that an operation that was
+/// reported as timed out doesn't modify it afterwards.
+fn check_cancelled() -> Result<()> {
+    match CancellationToken::current() {
+        Some(token) if token.is_cancelled() => Err(ErrorKind::Cancelled.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Writes a file so that it is either fully written or not at all.
+///
+/// The contents are written to a temporary file in the same directory, which
+/// is synced and then moved into place as specified by `mode`. Checking for an
+/// existing file and moving the temporary file are separate steps, so callers
+/// must hold the directory lock.
+fn write_atomic(path: &Path, contents: &[u8], mode: StoreMode) -> io::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let exists = match fs::symlink_metadata(path) {
+        Ok(_) => true,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+        Err(error) => return Err(error),
+    };
+    match (mode, exists) {
+        (StoreMode::CreateNew, true) => return Err(io::ErrorKind::AlreadyExists.into()),
+        (StoreMode::Replace, false) => return Err(io::ErrorKind::NotFound.into()),
+        _ => {}
+    }
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let temp_path = dir.join(format!(
+        "{TEMP_PREFIX}{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = write_temp(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+    sync_dir(dir)
+}
+
+fn write_temp(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Creates a directory and its parents, which only the current user can
+/// access.
+fn create_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir)
+}
+
+/// Checks that the directory, if it exists, isn't a symlink and can only be
+/// accessed by the current user.
+#[cfg(unix)]
+fn check_dir(dir: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(dir) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            return match error.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(Error::from(error)),
+            }
+        }
+    };
+
+    let problem = if metadata.file_type().is_symlink() {
+        "is a symlink"
+    } else if !metadata.is_dir() {
+        "is not a directory"
+    // SAFETY: `geteuid` has no preconditions and always succeeds.
+    } else if metadata.uid() != unsafe { libc::geteuid() } {
+        "is owned by another user"
+    } else if metadata.mode() & 0o077 != 0 {
+        "can be accessed by other users"
+    } else {
+        return Ok(());
+    };
+    Err(Error::new(
+        ErrorKind::InsecurePermissions,
+        format!("{} {problem}", dir.display()),
+    ))
+}
+
+#[cfg(not(unix))]
+fn check_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Syncs a directory, so that files moved into it persist after a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn remove_temp_files(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(TEMP_PREFIX))
+        {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn generate_key() -> Secret<[u8; 32]> {
+    let mut key = Secret::new([0; 32]);
+    OsRng.fill_bytes(key.expose_mut());
+    key
+}
+
+/// The parts of an item file.
+struct ItemFile<'a> {
+    /// The part of the file that is authenticated, up to the nonce.
+    associated_data: &'a [u8],
+    /// The encoded identifier of the item.
+    header: &'a [u8],
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> ItemFile<'a> {
+    fn parse(contents: &'a [u8]) -> Option<Self> {
+        let rest = contents.strip_prefix(MAGIC)?;
+        let (header, rest) = take_field(rest)?;
+        if rest.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        Some(Self {
+            associated_data: &contents[..contents.len() - rest.len()],
+            header,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+/// Encodes the class, service and username of an item unambiguously.
+fn encode_identifier(identifier: &Identifier) -> Vec<u8> {
+    let mut data = vec![match identifier.class {
+        Class::Generic => 0,
+        Class::Internet => 1,
+    }];
+    push_field(&mut data, identifier.service.as_bytes());
+    if let Some(username) = identifier.username {
+        push_field(&mut data, username.as_bytes());
+    }
+    data
+}
+
+fn decode_identifier(data: &[u8]) -> Option<OwnedIdentifier> {
+    let (&class, rest) = data.split_first()?;
+    let class = match class {
+        0 => Class::Generic,
+        1 => Class::Internet,
+        _ => return None,
+    };
+    let (service, rest) = take_field(rest)?;
+    let username = match rest {
+        [] => None,
+        _ => match take_field(rest)? {
+            (username, []) => Some(String::from_utf8(username.to_owned()).ok()?),
+            _ => return None,
+        },
+    };
+    Some(OwnedIdentifier {
+        service: String::from_utf8(service.to_owned()).ok()?,
+        username,
+        class,
+    })
+}
+
+/// Appends a length-prefixed field, so that the encoding is unambiguous.
+fn push_field(data: &mut Vec<u8>, field: &[u8]) {
+    data.extend_from_slice(&(field.len() as u64).to_le_bytes());
+    data.extend_from_slice(field);
+}
+
+/// Splits a length-prefixed field from the start of `data`.
+fn take_field(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = data.split_first_chunk::<8>()?;
+    let len = usize::try_from(u64::from_le_bytes(*len)).ok()?;
+    (len <= rest.len()).then(|| rest.split_at(len))
+}
+
+/// Returns the name of the item's file, which is the hex-encoded SHA-256
+/// hash of its encoded identifier.
+fn file_name(identifier: &Identifier) -> String {
+    Sha256::digest(encode_identifier(identifier))
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Returns whether `name` is used for something other than an item, such as
+/// the lock file or temporary files.
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+pub(crate) fn is_reserved_name(name: &str) -> bool {
+    name == LOCK_FILE || name == METADATA_DIR || name.starts_with(TEMP_PREFIX)
+}
+
+/// Returns whether `contents` are those of an item file.
+#[cfg_attr(not(target_os = "android"), allow(dead_code))]
+pub(crate) fn is_item_file(contents: &[u8]) -> bool {
+    ItemFile::parse(contents).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+
+    use super::*;
+    use crate::{Keychain, Task};
+
+    /// An empty directory that is removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("robius-keychain-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&path);
+            create_dir(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn keychain(dir: &TempDir) -> Keychain {
+        Keychain::new(FileKeychain::new(
+            dir.0.join("items"),
+            SoftwareKeyProvider::new([7; 32]),
+        ))
+    }
+
+    #[test]
+    fn contract() {
+        let dir = TempDir::new("contract");
+        crate::backend::check_backend(&FileKeychain::new(
+            dir.0.join("items"),
+            SoftwareKeyProvider::new([7; 32]),
+        ));
+    }
+
+    #[test]
+    fn timestamps() {
+        let dir = TempDir::new("timestamps");
+        let keychain = keychain(&dir);
+        let id = keychain
+            .store(KeychainItemBuilder::new("service", "first"))
+            .unwrap();
+        let created = keychain.metadata(&id).unwrap().unwrap().created;
+        assert!(created.is_some());
+
+        thread::sleep(Duration::from_millis(10));
+        keychain
+            .update(&id, UpdateOptions::new().secret("second"))
+            .unwrap();
+        keychain
+            .store(KeychainItemBuilder::new("service", "third").mode(StoreMode::Replace))
+            .unwrap();
+        let id = keychain
+            .update(&id, UpdateOptions::new().username("renamed"))
+            .unwrap();
+
+        let metadata = keychain.metadata(&id).unwrap().unwrap();
+        assert_eq!(metadata.created, created);
+        assert!(metadata.modified > created);
+
+        // Updating only the metadata doesn't rewrite the item's file.
+        thread::sleep(Duration::from_millis(10));
+        keychain
+            .update(&id, UpdateOptions::new().comment("comment"))
+            .unwrap();
+        let updated = keychain.metadata(&id).unwrap().unwrap();
+        assert_eq!(updated.created, created);
+        assert!(updated.modified > metadata.modified);
+    }
+
+    #[test]
+    fn timed_out_store_is_not_written() {
+        let dir = TempDir::new("timeout");
+        let backend = Arc::new(
+            FileKeychain::new(dir.0.join("items"), SoftwareKeyProvider::new([7; 32]))
+                .with_lock_wait(LockWait::Block),
+        );
+        backend
+            .store(&KeychainItemBuilder::new("first", "secret"))
+            .unwrap();
+
+        let lock = File::open(dir.0.join("items").join(LOCK_FILE)).unwrap();
+        lock.lock().unwrap();
+        let (done, finished) = mpsc::channel();
+        let worker = backend.clone();
+        let task = Task::spawn(
+            move || {
+                let result = worker.store(&KeychainItemBuilder::new("second", "secret"));
+                done.send(result.is_ok()).unwrap();
+                result
+            },
+            Some(Duration::from_millis(50)),
+        );
+        assert_eq!(task.wait().unwrap_err().kind(), ErrorKind::TimedOut);
+
+        // The worker gives up waiting for the lock once it is cancelled.
+        assert!(!finished.recv().unwrap());
+        drop(lock);
+        assert!(backend.load(&identifier("second", None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn cancelled_lock_wait() {
+        let dir = TempDir::new("cancel");
+        let backend = FileKeychain::new(dir.0.join("items"), SoftwareKeyProvider::new([7; 32]))
+            .with_lock_wait(LockWait::Block);
+        backend
+            .store(&KeychainItemBuilder::new("first", "secret"))
+            .unwrap();
+
+        let lock = File::open(dir.0.join("items").join(LOCK_FILE)).unwrap();
+        lock.lock().unwrap();
+        let token = CancellationToken::new();
+        let result = thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                token.scope(|| backend.store(&KeychainItemBuilder::new("second", "secret")))
+            });
+            token.cancel();
+            worker.join().unwrap()
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Cancelled);
+        drop(lock);
+        assert!(backend.load(&identifier("second", None)).unwrap().is_none());
+    }
+
+    fn identifier<'a>(service: &'a str, username: Option<&'a str>) -> Identifier<'a> {
+        Identifier {
+            service,
+            username,
+            class: Class::Generic,
+        }
+    }
+
+    #[test]
+    fn encrypt_roundtrip() {
+        let dir = TempDir::new("encrypt");
+        let keychain = FileKeychain::new(&dir.0, SoftwareKeyProvider::new([7; 32]));
+        let id = identifier("service", Some("username"));
+
+        let contents = keychain.encrypt(&id, b"secret").unwrap();
+        assert!(!contents.windows(6).any(|window| window == b"secret"));
+        let secret = keychain.decrypt(&id, &contents).unwrap();
+        assert_eq!(secret.expose(), b"secret");
+
+        let mut tampered = contents.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let error = keychain.decrypt(&id, &tampered).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let other_key = FileKeychain::new(&dir.0, SoftwareKeyProvider::new([8; 32]));
+        let error = other_key.decrypt(&id, &contents).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn swapped_file_is_rejected() {
+        let dir = TempDir::new("swap");
+        let backend = FileKeychain::new(dir.0.join("items"), SoftwareKeyProvider::new([7; 32]));
+        let a = identifier("service", Some("a"));
+        let b = identifier("service", Some("b"));
+        backend
+            .store(&KeychainItemBuilder::new("service", "a").username("a"))
+            .unwrap();
+        backend
+            .store(&KeychainItemBuilder::new("service", "b").username("b"))
+            .unwrap();
+
+        // A file copied over another item is detected by its header.
+        fs::copy(backend.file(&a), backend.file(&b)).unwrap();
+        let error = backend.load(&b).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // Rewriting the header fails authentication, as it is associated data.
+        let contents = fs::read(backend.file(&a)).unwrap();
+        let item = ItemFile::parse(&contents).unwrap();
+        let mut forged = MAGIC.to_vec();
+        push_field(&mut forged, &encode_identifier(&b));
+        forged.extend_from_slice(item.nonce);
+        forged.extend_from_slice(item.ciphertext);
+        let error = backend.decrypt(&b, &forged).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn identifier_encoding() {
+        let identifiers = [
+            identifier("service", None),
+            identifier("service", Some("")),
+            identifier("", Some("service")),
+            identifier("a/b\\c:d\0", Some("\u{1f}username")),
+            Identifier {
+                service: "service",
+                username: Some("username"),
+                class: Class::Internet,
+            },
+        ];
+        for (i, id) in identifiers.iter().enumerate() {
+            let encoded = encode_identifier(id);
+            assert_eq!(decode_identifier(&encoded), Some(id.to_owned()));
+            assert!(decode_identifier(&encoded[..encoded.len() - 1]).is_none());
+            assert!(decode_identifier(&[&encoded[..], &[0]].concat()).is_none());
+
+            let name = file_name(id);
+            assert_eq!(name.len(), 64);
+            assert!(name.bytes().all(|byte| byte.is_ascii_hexdigit()));
+            for other in &identifiers[i + 1..] {
+                assert_ne!(name, file_name(other));
+            }
+        }
+
+        assert!(decode_identifier(&[2]).is_none());
+        let mut data = Vec::new();
+        push_field(&mut data, b"field");
+        assert_eq!(take_field(&data), Some((&b"field"[..], &[][..])));
+        assert_eq!(take_field(&data[..data.len() - 1]), None);
+        assert_eq!(take_field(&u64::MAX.to_le_bytes()), None);
+        assert_eq!(take_field(&[0; 7]), None);
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let dir = TempDir::new("lock");
+        let keychain = |lock_wait| {
+            Keychain::new(
+                FileKeychain::new(dir.0.join("items"), SoftwareKeyProvider::new([7; 32]))
+                    .with_lock_wait(lock_wait),
+            )
+        };
+        keychain(LockWait::Block)
+            .store(KeychainItemBuilder::new("service", "secret"))
+            .unwrap();
+
+        let lock = File::open(dir.0.join("items").join(LOCK_FILE)).unwrap();
+        lock.lock().unwrap();
+        let item = || KeychainItemBuilder::new("service", "other");
+        let error = keychain(LockWait::Timeout(Duration::ZERO))
+            .store(item())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Locked);
+
+        let start = Instant::now();
+        let error = keychain(LockWait::Timeout(Duration::from_millis(100)))
+            .store(item())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Locked);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Readers don't take the lock.
+        let id = identifier("service", None);
+        let keychain = keychain(LockWait::default());
+        assert_eq!(keychain.load(&id).unwrap().unwrap().expose(), "secret");
+
+        drop(lock);
+        keychain.store(item()).unwrap();
+    }
+
+    #[test]
+    fn key_file() {
+        let dir = TempDir::new("key");
+        let path = dir.0.join("keys").join("key");
+        let keys = thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| KeyFile::new(&path).key().unwrap()))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| *thread.join().unwrap().expose())
+                .collect::<Vec<_>>()
+        });
+        assert!(keys.iter().all(|key| *key == keys[0]));
+        assert_eq!(fs::read(&path).unwrap(), keys[0]);
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+
+        // A key file left empty by a crash is written again.
+        fs::write(&path, []).unwrap();
+        let key = KeyFile::new(&path).key().unwrap();
+        assert_ne!(*key.expose(), keys[0]);
+        assert_eq!(fs::read(&path).unwrap(), key.expose());
+
+        fs::write(&path, [0; 31]).unwrap();
+        let error = KeyFile::new(&path).key().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
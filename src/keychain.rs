@@ -0,0 +1,343 @@
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "serde")]
+use crate::Codec;
+use crate::{
+    owned::{OwnedKeychainItemBuilder, OwnedSearchOptions, OwnedUpdateOptions},
+    Backend, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier,
+    Platform, Result, SearchOptions, Secret, Task, UpdateOptions,
+};
+
+static DEFAULT: RwLock<Option<Keychain>> = RwLock::new(None);
+
+/// A handle to a keychain backend.
+///
+/// [`KeychainItemBuilder::store`] and the methods on [`Identifier`] operate on
+/// the default keychain, which is the [`Platform`] keychain unless replaced
+/// with [`Keychain::set_default`]. A `Keychain` can also be used directly to
+/// operate on a specific backend.
+///
+/// Operations can also be run on a worker thread with the `_async` methods,
+/// which return a [`Task`] that can be awaited on any executor. The backends
+/// are blocking, so each task occupies a thread until its operation returns.
+///
+/// Cloning a `Keychain` is cheap; clones share the same backend.
+#[derive(Clone)]
+pub struct Keychain {
+    backend: Arc<dyn Backend>,
+    timeout: Option<Duration>,
+}
+
+impl Keychain {
+    /// Creates a new `Keychain` backed by `backend`.
+    pub fn new<B>(backend: B) -> Self
+    where
+        B: Backend + 'static,
+    {
+        Self {
+            backend: Arc::new(backend),
+            timeout: None,
+        }
+    }
+
+    /// Creates a new `Keychain` backed by the platform keychain.
+    pub fn platform() -> Self {
+        Self::new(Platform)
+    }
+
+    /// Sets a timeout for operations on the keychain.
+    ///
+    /// Operations that don't complete within `timeout`, e.g. because the
+    /// Secret Service daemon is hung or an unlock prompt is left unanswered,
+    /// fail with an [`ErrorKind::TimedOut`] error. To achieve this, operations
+    /// run on a worker thread and are cancelled once the timeout expires; see
+    /// [`CancellationToken`](crate::CancellationToken) for what cancellation
+    /// means on each platform.
+    ///
+    /// The timeout can be applied to a single call by setting it on a clone:
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    ///
+    /// use robius_keychain::{Class, Identifier, Keychain};
+    ///
+    /// let id = Identifier {
+    ///     service: "hello_world",
+    ///     username: Some("username"),
+    ///     class: Class::Generic,
+    /// };
+    /// let secret = Keychain::default()
+    ///     .with_timeout(Duration::from_secs(10))
+    ///     .load(&id);
+    /// ```
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Replaces the keychain used by [`KeychainItemBuilder::store`] and the
+    /// methods on [`Identifier`].
+    pub fn set_default(keychain: Keychain) {
+        *DEFAULT.write().unwrap_or_else(|e| e.into_inner()) = Some(keychain);
+    }
+
+    /// Stores the item in the keychain.
+    pub fn store<'a>(&self, item: KeychainItemBuilder<'a>) -> Result<Identifier<'a>> {
+        let identifier = Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        };
+        match self.timeout {
+            Some(_) => {
+                self.store_async(item).wait()?;
+            }
+            None => self.backend.store(&item)?,
+        }
+        Ok(identifier)
+    }
+
+    /// Loads the secret of an item, returning `None` if the item does not
+    /// exist.
+    ///
+    /// Returns an [`ErrorKind::InvalidData`] error if the secret is not valid
+    /// UTF-8.
+    pub fn load(&self, identifier: &Identifier) -> Result<Option<Secret>> {
+        into_string(self.load_bytes(identifier)?)
+    }
+
+    /// Loads a binary secret, returning `None` if the item does not exist.
+    pub fn load_bytes(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        match self.timeout {
+            Some(_) => self.load_bytes_async(identifier).wait(),
+            None => self.backend.load(identifier),
+        }
+    }
+
+    /// Loads the non-secret information about an item, returning `None` if the
+    /// item does not exist.
+    ///
+    /// See [`Identifier::metadata`].
+    pub fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        match self.timeout {
+            Some(_) => self.metadata_async(identifier).wait(),
+            None => self.backend.metadata(identifier),
+        }
+    }
+
+    /// Updates an item, returning its new identifier.
+    pub fn update<'a>(
+        &self,
+        identifier: &Identifier<'a>,
+        options: UpdateOptions<'a>,
+    ) -> Result<Identifier<'a>> {
+        let new_id = updated(identifier, &options);
+        match self.timeout {
+            Some(_) => {
+                self.update_async(identifier, options).wait()?;
+            }
+            None => self.backend.update(identifier, &options)?,
+        }
+        Ok(new_id)
+    }
+
+    /// Deletes an item.
+    pub fn delete(&self, identifier: &Identifier) -> Result<()> {
+        match self.timeout {
+            Some(_) => self.delete_async(identifier).wait(),
+            None => self.backend.delete(identifier),
+        }
+    }
+
+    /// Returns the identifiers of all items matching `options`.
+    pub fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        match self.timeout {
+            Some(_) => self.search_async(options).wait(),
+            None => search(&*self.backend, options),
+        }
+    }
+
+    /// Encodes `value` with `codec` and stores it as the secret of the item.
+    ///
+    /// The encoded value replaces the secret of `item`. The encoded bytes are
+    /// zeroed once the item is stored.
+    #[cfg(feature = "serde")]
+    pub fn store_encoded<'a, C, T>(
+        &self,
+        item: KeychainItemBuilder<'a>,
+        codec: &C,
+        value: &T,
+    ) -> Result<Identifier<'a>>
+    where
+        C: Codec,
+        T: Serialize + ?Sized,
+    {
+        let identifier = Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        };
+        let secret = Secret::new(codec.encode(value)?);
+        self.store(KeychainItemBuilder {
+            secret: secret.expose(),
+            ..item
+        })?;
+        Ok(identifier)
+    }
+
+    /// Loads the secret of an item and decodes it with `codec`, returning
+    /// `None` if the item does not exist.
+    ///
+    /// Returns an [`ErrorKind::Decode`] error if the secret can't be decoded.
+    #[cfg(feature = "serde")]
+    pub fn load_decoded<C, T>(&self, identifier: &Identifier, codec: &C) -> Result<Option<T>>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        match self.load_bytes(identifier)? {
+            Some(secret) => codec.decode(secret.expose()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the item in the keychain on a worker thread.
+    ///
+    /// See [`Keychain::store`].
+    pub fn store_async(&self, item: KeychainItemBuilder) -> Task<OwnedIdentifier> {
+        let item = OwnedKeychainItemBuilder::from(&item);
+        self.spawn(move |backend| {
+            let item = item.as_builder();
+            backend.store(&item)?;
+            Ok(OwnedIdentifier {
+                service: item.service.to_owned(),
+                username: item.username.map(ToOwned::to_owned),
+                class: item.class,
+            })
+        })
+    }
+
+    /// Loads the secret of an item on a worker thread.
+    ///
+    /// See [`Keychain::load`].
+    pub fn load_async(&self, identifier: &Identifier) -> Task<Option<Secret>> {
+        let identifier = identifier.to_owned();
+        self.spawn(move |backend| into_string(backend.load(&identifier.as_ref())?))
+    }
+
+    /// Loads a binary secret on a worker thread.
+    ///
+    /// See [`Keychain::load_bytes`].
+    pub fn load_bytes_async(&self, identifier: &Identifier) -> Task<Option<Secret<Vec<u8>>>> {
+        let identifier = identifier.to_owned();
+        self.spawn(move |backend| backend.load(&identifier.as_ref()))
+    }
+
+    /// Loads the non-secret information about an item on a worker thread.
+    ///
+    /// See [`Keychain::metadata`].
+    pub fn metadata_async(&self, identifier: &Identifier) -> Task<Option<ItemMetadata>> {
+        let identifier = identifier.to_owned();
+        self.spawn(move |backend| backend.metadata(&identifier.as_ref()))
+    }
+
+    /// Updates an item on a worker thread, returning its new identifier.
+    ///
+    /// See [`Keychain::update`].
+    pub fn update_async(
+        &self,
+        identifier: &Identifier,
+        options: UpdateOptions,
+    ) -> Task<OwnedIdentifier> {
+        let identifier = identifier.to_owned();
+        let options = OwnedUpdateOptions::from(&options);
+        self.spawn(move |backend| {
+            let identifier = identifier.as_ref();
+            let options = options.as_options();
+            backend.update(&identifier, &options)?;
+            Ok(updated(&identifier, &options).to_owned())
+        })
+    }
+
+    /// Deletes an item on a worker thread.
+    ///
+    /// See [`Keychain::delete`].
+    pub fn delete_async(&self, identifier: &Identifier) -> Task<()> {
+        let identifier = identifier.to_owned();
+        self.spawn(move |backend| backend.delete(&identifier.as_ref()))
+    }
+
+    /// Searches the keychain on a worker thread.
+    ///
+    /// See [`Keychain::search`].
+    pub fn search_async(&self, options: &SearchOptions) -> Task<Vec<OwnedIdentifier>> {
+        let options = OwnedSearchOptions::from(options);
+        self.spawn(move |backend| search(backend, &options.as_options()))
+    }
+
+    /// Runs `f` on a worker thread, applying the timeout.
+    fn spawn<T, F>(&self, f: F) -> Task<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Backend) -> Result<T> + Send + 'static,
+    {
+        let backend = self.backend.clone();
+        Task::spawn(move || f(&*backend), self.timeout)
+    }
+}
+
+impl Default for Keychain {
+    /// Returns the default keychain.
+    ///
+    /// See [`Keychain::set_default`].
+    fn default() -> Self {
+        DEFAULT
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_else(Self::platform)
+    }
+}
+
+impl fmt::Debug for Keychain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keychain")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+fn search(backend: &dyn Backend, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    let mut identifiers = backend.search(options)?;
+    identifiers.retain(|identifier| options.matches(&identifier.as_ref()));
+    if let Some(limit) = options.limit {
+        identifiers.truncate(limit);
+    }
+    Ok(identifiers)
+}
+
+/// Returns the identifier of an item after it is updated with `options`.
+fn updated<'a>(identifier: &Identifier<'a>, options: &UpdateOptions<'a>) -> Identifier<'a> {
+    Identifier {
+        service: options.service.unwrap_or(identifier.service),
+        username: options.username.or(identifier.username),
+        class: options.class.unwrap_or(identifier.class),
+    }
+}
+
+fn into_string(secret: Option<Secret<Vec<u8>>>) -> Result<Option<Secret>> {
+    match secret {
+        Some(secret) => match secret.into_string() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(error) => Err(Error::new(ErrorKind::InvalidData, error)),
+        },
+        None => Ok(None),
+    }
+}
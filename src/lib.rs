@@ -2,19 +2,20 @@
 //!
 //! # Examples
 //!
-//! ```
+//! ```no_run
 //! let secret = "hunter2";
-//! 
+//!
 //! let id = robius_keychain::KeychainItemBuilder::new("hello_world", &secret)
 //!     .username("username")
 //!     .store()
 //!     .expect("failed to create keychain item");
-//! 
+//!
 //! assert_eq!(
 //!     secret,
 //!     id.load()
 //!         .expect("failed to search keychain")
 //!         .expect("found no keychain items")
+//!         .expose()
 //! );
 //! // Alternatively we can recreate the Identifier struct.
 //! assert_eq!(
@@ -27,8 +28,9 @@
 //!     .load()
 //!     .expect("failed to search keychain")
 //!     .expect("found no keychain items")
+//!     .expose()
 //! );
-//! 
+//!
 //! // If we don't update the service or username, then the old id will still be
 //! // valid.
 //! let id = id
@@ -38,48 +40,158 @@
 //!             .secret("hunter 3"),
 //!     )
 //!     .expect("failed to update keychain item");
-//! 
+//!
 //! id.delete().expect("failed to delete keychain item");
 //! ```
+//!
+//! # Serialization
+//!
+//! With the `serde` feature, the non-secret types implement `Serialize` and
+//! `Deserialize`. Secrets are never serialized; the `secret` field of
+//! [`UpdateOptions`] and [`OwnedUpdateOptions`] is skipped.
+//!
+//! The borrowed [`Identifier`], [`UpdateOptions`] and [`SearchOptions`] only
+//! implement `Serialize`, as borrowed strings can't hold escaped characters.
+//! Deserialize the same representation into [`OwnedIdentifier`] and
+//! [`OwnedUpdateOptions`] instead.
+//!
+//! The representation is stable across releases:
+//!
+//! - [`Class`], [`StoreMode`] and [`ErrorKind`] are strings such as
+//!   `"generic"`, `"create_new"` and `"not_found"`.
+//! - [`Identifier`] and [`OwnedIdentifier`] are maps with a `service`, an
+//!   optional `username` and an optional `class`, which defaults to
+//!   `"generic"`:
+//!   ```json
+//!   { "service": "hello_world", "username": "username", "class": "generic" }
+//!   ```
+//! - [`UpdateOptions`], [`SearchOptions`] and [`ItemMetadata`] are maps with
+//!   their field names as keys, where every field is optional. Attributes are
+//!   lists of `[key, value]` pairs in options, and maps in [`ItemMetadata`].
+//!
+//! Typed values can also be stored as secrets with
+//! `KeychainItemBuilder::store_encoded` and loaded with
+//! `Identifier::load_decoded`, using a `Codec`. The `json` feature adds the
+//! `Json` codec and the `store_json` and `load_json` shorthands.
 
+mod backend;
+mod cancel;
+#[cfg(feature = "serde")]
+mod codec;
 mod error;
+#[cfg(any(feature = "file", target_os = "android"))]
+mod file;
+mod keychain;
+#[cfg(feature = "memory")]
+mod memory;
+mod metadata;
+mod owned;
+mod secret;
 mod sys;
+mod task;
+
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
 
-pub use error::{Error, Result};
+pub use backend::Backend;
+pub use cancel::CancellationToken;
+#[cfg(feature = "serde")]
+pub use codec::Codec;
+#[cfg(feature = "json")]
+pub use codec::Json;
+pub use error::{Error, ErrorKind, Result};
+#[cfg(any(feature = "file", target_os = "android"))]
+pub use file::{
+    FileKeychain, FileKeychainBuilder, KeyFile, KeyProvider, LockWait, SoftwareKeyProvider,
+};
+pub use keychain::Keychain;
+#[cfg(feature = "memory")]
+pub use memory::MemoryKeychain;
+pub use metadata::ItemMetadata;
+pub use owned::{OwnedKeychainItemBuilder, OwnedUpdateOptions};
+pub use secret::Secret;
+pub use sys::Platform;
+#[cfg(all(target_os = "linux", feature = "keyutils"))]
+pub use sys::{KernelKeyring, KernelKeyringBuilder, KeyringScope};
+#[cfg(all(target_os = "linux", feature = "secret-service"))]
+pub use sys::{SecretService, SecretServiceBuilder, SessionEncryption};
+pub use task::Task;
 
 /// Builder to add an item to the keychain.
 ///
+/// The fields can be read by [`Backend`] implementations, but the builder can
+/// only be constructed using [`KeychainItemBuilder::new`].
+///
 /// See the crate-level docs for more information.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub struct KeychainItemBuilder<'a> {
-    service: &'a str,
-    secret: &'a str,
-    class: Class,
-    username: Option<&'a str>,
+    pub service: &'a str,
+    pub secret: &'a [u8],
+    pub class: Class,
+    pub username: Option<&'a str>,
+    pub label: Option<&'a str>,
+    pub comment: Option<&'a str>,
+    pub attributes: Vec<(&'a str, &'a str)>,
+    pub mode: StoreMode,
 }
 
 /// The class of the keychain item.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Class {
+    #[default]
     Generic,
     Internet,
     // Certificate,
 }
 
-impl Default for Class {
-    fn default() -> Self {
-        Self::Generic
-    }
+/// How to store an item if an item with the same identifier already exists.
+///
+/// An existing item is replaced entirely, including its label, comment and
+/// attributes.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum StoreMode {
+    /// Only store the item if it doesn't exist, returning an
+    /// [`ErrorKind::Duplicate`] error otherwise.
+    CreateNew,
+    /// Only store the item if it already exists, returning an
+    /// [`ErrorKind::NotFound`] error otherwise.
+    Replace,
+    /// Store the item, replacing it if it already exists.
+    #[default]
+    Upsert,
 }
 
 impl<'a> KeychainItemBuilder<'a> {
     /// Creates a new `KeychainItemBuilder.
-    pub fn new(service: &'a str, secret: &'a str) -> Self {
+    ///
+    /// The secret can be a string, a byte slice or a [`Secret`].
+    pub fn new<S>(service: &'a str, secret: &'a S) -> Self
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        Self::from_bytes(service, secret.as_ref())
+    }
+
+    /// Creates a new `KeychainItemBuilder` with a binary secret.
+    ///
+    /// The secret can be loaded using [`Identifier::load_bytes`].
+    pub fn from_bytes(service: &'a str, secret: &'a [u8]) -> Self {
         Self {
             service,
             secret,
             class: Class::default(),
             username: None,
+            label: None,
+            comment: None,
+            attributes: Vec::new(),
+            mode: StoreMode::default(),
         }
     }
 
@@ -95,24 +207,153 @@ impl<'a> KeychainItemBuilder<'a> {
         self
     }
 
-    /// Stores the item in the keychain.
+    /// Sets the label for the keychain item.
+    ///
+    /// The label is the name shown by keyring managers such as Seahorse or
+    /// Keychain Access. If it is not set, a label is derived from the service
+    /// and username where the platform requires one.
+    ///
+    /// The label is not stored by the Windows Credential Manager.
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Sets the comment for the keychain item.
+    ///
+    /// The comment is a human-readable description shown by keyring managers.
+    /// On Linux, it is stored as the reserved `comment` attribute.
+    pub fn comment(mut self, comment: &'a str) -> Self {
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Adds a custom attribute to the keychain item.
+    ///
+    /// Attributes are stored alongside the item and can be used to narrow down
+    /// a search with [`SearchOptions::attribute`]. Adding an attribute with the
+    /// same key twice keeps the last value.
+    ///
+    /// Attributes are not supported by the Apple keychain. On Linux, the keys
+    /// `service`, `username`, `comment` and those starting with `xdg:` are
+    /// reserved.
+    pub fn attribute(mut self, key: &'a str, value: &'a str) -> Self {
+        self.attributes.push((key, value));
+        self
+    }
+
+    /// Sets how to store the item if it already exists.
+    ///
+    /// Defaults to [`StoreMode::Upsert`].
+    pub fn mode(mut self, mode: StoreMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Stores the item in the default keychain.
+    ///
+    /// Use [`Keychain::store`] to store the item in a specific keychain.
     pub fn store(self) -> Result<Identifier<'a>> {
-        let identifier = Identifier {
-            service: self.service,
-            username: self.username,
-            class: self.class,
-        };
-        sys::store(self)?;
-        Ok(identifier)
+        Keychain::default().store(self)
+    }
+
+    /// Stores the item in the default keychain, replacing any existing item.
+    ///
+    /// This is equivalent to storing with [`StoreMode::Upsert`].
+    pub fn store_or_replace(self) -> Result<Identifier<'a>> {
+        self.mode(StoreMode::Upsert).store()
+    }
+
+    /// Stores the item in the default keychain unless it already exists.
+    ///
+    /// This is equivalent to storing with [`StoreMode::CreateNew`], and
+    /// returns an [`ErrorKind::Duplicate`] error if the item exists.
+    pub fn store_if_absent(self) -> Result<Identifier<'a>> {
+        self.mode(StoreMode::CreateNew).store()
+    }
+
+    /// Stores `value` as JSON in the default keychain.
+    ///
+    /// The encoded value replaces the secret of the builder, which can be left
+    /// empty:
+    ///
+    /// ```no_run
+    /// use robius_keychain::KeychainItemBuilder;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Tokens {
+    ///     access_token: String,
+    ///     refresh_token: String,
+    ///     expires_at: u64,
+    /// }
+    ///
+    /// let tokens = Tokens {
+    ///     access_token: "access".to_owned(),
+    ///     refresh_token: "refresh".to_owned(),
+    ///     expires_at: 1_700_000_000,
+    /// };
+    /// let id = KeychainItemBuilder::new("oauth", "")
+    ///     .username("username")
+    ///     .store_json(&tokens)
+    ///     .expect("failed to create keychain item");
+    ///
+    /// let tokens: Tokens = id
+    ///     .load_json()
+    ///     .expect("failed to load keychain item")
+    ///     .expect("found no keychain items");
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn store_json<T>(self, value: &T) -> Result<Identifier<'a>>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.store_encoded(&Json, value)
+    }
+
+    /// Stores `value` in the default keychain, encoded with `codec`.
+    ///
+    /// See [`Keychain::store_encoded`].
+    #[cfg(feature = "serde")]
+    pub fn store_encoded<C, T>(self, codec: &C, value: &T) -> Result<Identifier<'a>>
+    where
+        C: Codec,
+        T: Serialize + ?Sized,
+    {
+        Keychain::default().store_encoded(self, codec, value)
     }
 }
 
-#[derive(Clone, Debug, Default)]
+impl fmt::Debug for KeychainItemBuilder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeychainItemBuilder")
+            .field("service", &self.service)
+            .field("secret", &"[REDACTED]")
+            .field("class", &self.class)
+            .field("username", &self.username)
+            .field("label", &self.label)
+            .field("comment", &self.comment)
+            .field("attributes", &self.attributes)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+/// Options to update an item in the keychain.
+///
+/// Fields that are `None` are left unchanged.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub struct UpdateOptions<'a> {
-    service: Option<&'a str>,
-    secret: Option<&'a str>,
-    class: Option<Class>,
-    username: Option<&'a str>,
+    pub service: Option<&'a str>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub secret: Option<&'a [u8]>,
+    pub class: Option<Class>,
+    pub username: Option<&'a str>,
+    pub label: Option<&'a str>,
+    pub comment: Option<&'a str>,
+    pub attributes: Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> UpdateOptions<'a> {
@@ -127,7 +368,17 @@ impl<'a> UpdateOptions<'a> {
     }
 
     /// Sets the secret for the keychain item.
-    pub fn secret(mut self, secret: &'a str) -> Self {
+    ///
+    /// The secret can be a string, a byte slice or a [`Secret`].
+    pub fn secret<S>(self, secret: &'a S) -> Self
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        self.secret_bytes(secret.as_ref())
+    }
+
+    /// Sets a binary secret for the keychain item.
+    pub fn secret_bytes(mut self, secret: &'a [u8]) -> Self {
         self.secret = Some(secret);
         self
     }
@@ -143,11 +394,141 @@ impl<'a> UpdateOptions<'a> {
         self.username = Some(username);
         self
     }
+
+    /// Sets the label for the keychain item.
+    ///
+    /// See [`KeychainItemBuilder::label`].
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Sets the comment for the keychain item.
+    ///
+    /// See [`KeychainItemBuilder::comment`].
+    pub fn comment(mut self, comment: &'a str) -> Self {
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Sets a custom attribute on the keychain item.
+    ///
+    /// Other attributes of the item are left unchanged.
+    ///
+    /// See [`KeychainItemBuilder::attribute`].
+    pub fn attribute(mut self, key: &'a str, value: &'a str) -> Self {
+        self.attributes.push((key, value));
+        self
+    }
+}
+
+impl fmt::Debug for UpdateOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UpdateOptions")
+            .field("service", &self.service)
+            .field("secret", &self.secret.map(|_| "[REDACTED]"))
+            .field("class", &self.class)
+            .field("username", &self.username)
+            .field("label", &self.label)
+            .field("comment", &self.comment)
+            .field("attributes", &self.attributes)
+            .finish()
+    }
+}
+
+/// Options to search for items in the keychain.
+///
+/// Fields that are `None` match any value.
+///
+/// ```no_run
+/// let accounts = robius_keychain::SearchOptions::new()
+///     .service("hello_world")
+///     .search()
+///     .expect("failed to search keychain");
+///
+/// for account in accounts {
+///     println!("{:?}", account.username);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct SearchOptions<'a> {
+    pub service: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub username_prefix: Option<&'a str>,
+    pub class: Option<Class>,
+    pub attributes: Vec<(&'a str, &'a str)>,
+    pub limit: Option<usize>,
+}
+
+impl<'a> SearchOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches items with the given service.
+    pub fn service(mut self, service: &'a str) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    /// Only matches items with the given username.
+    pub fn username(mut self, username: &'a str) -> Self {
+        self.username = Some(username);
+        self
+    }
+
+    /// Only matches items with a username starting with `prefix`.
+    pub fn username_prefix(mut self, prefix: &'a str) -> Self {
+        self.username_prefix = Some(prefix);
+        self
+    }
+
+    /// Only matches items with the given class.
+    pub fn class(mut self, class: Class) -> Self {
+        self.class = Some(class);
+        self
+    }
+
+    /// Only matches items with the given custom attribute.
+    ///
+    /// See [`KeychainItemBuilder::attribute`].
+    pub fn attribute(mut self, key: &'a str, value: &'a str) -> Self {
+        self.attributes.push((key, value));
+        self
+    }
+
+    /// Sets the maximum number of items to return.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Returns whether `identifier` matches the options.
+    ///
+    /// The attributes and the limit are not taken into account.
+    pub fn matches(&self, identifier: &Identifier) -> bool {
+        self.service.is_none_or(|s| s == identifier.service)
+            && self.username.is_none_or(|u| Some(u) == identifier.username)
+            && self
+                .username_prefix
+                .is_none_or(|p| identifier.username.is_some_and(|u| u.starts_with(p)))
+            && self.class.is_none_or(|c| c == identifier.class)
+    }
+
+    /// Searches the default keychain for matching items.
+    ///
+    /// Use [`Keychain::search`] to search a specific keychain.
+    pub fn search(&self) -> Result<Vec<OwnedIdentifier>> {
+        Keychain::default().search(self)
+    }
 }
 
 // An identifier returned by [`KeychainItemBuilder::store`] to later [`load`]
 // the item.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Identifier<'a> {
     pub service: &'a str,
     pub username: Option<&'a str>,
@@ -166,34 +547,89 @@ impl<'a> Identifier<'a> {
     }
 
     /// See the crate-level docs for more information.
-    pub fn load(&self) -> Result<Option<String>> {
-        sys::load(self)
+    pub fn load(&self) -> Result<Option<Secret>> {
+        Keychain::default().load(self)
+    }
+
+    /// Loads a binary secret.
+    ///
+    /// Unlike [`Identifier::load`], this does not require the secret to be
+    /// valid UTF-8.
+    pub fn load_bytes(&self) -> Result<Option<Secret<Vec<u8>>>> {
+        Keychain::default().load_bytes(self)
+    }
+
+    /// Loads a secret stored with [`KeychainItemBuilder::store_json`].
+    ///
+    /// Returns an [`ErrorKind::Decode`] error if the secret is not valid JSON
+    /// for `T`.
+    #[cfg(feature = "json")]
+    pub fn load_json<T>(&self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.load_decoded(&Json)
+    }
+
+    /// Loads a secret stored with [`KeychainItemBuilder::store_encoded`].
+    ///
+    /// See [`Keychain::load_decoded`].
+    #[cfg(feature = "serde")]
+    pub fn load_decoded<C, T>(&self, codec: &C) -> Result<Option<T>>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        Keychain::default().load_decoded(self, codec)
+    }
+
+    /// Loads the timestamps, label, class and attributes of the item,
+    /// returning `None` if the item does not exist.
+    ///
+    /// The secret is not loaded, so this doesn't prompt the user on platforms
+    /// that require confirmation to access secrets.
+    ///
+    /// ```no_run
+    /// use robius_keychain::{Class, Identifier};
+    ///
+    /// let id = Identifier {
+    ///     service: "hello_world",
+    ///     username: Some("username"),
+    ///     class: Class::Generic,
+    /// };
+    /// if let Some(metadata) = id.metadata().expect("failed to search keychain") {
+    ///     println!("last rotated: {:?}", metadata.modified);
+    /// }
+    /// ```
+    pub fn metadata(&self) -> Result<Option<ItemMetadata>> {
+        Keychain::default().metadata(self)
     }
 
     pub fn update(&self, options: UpdateOptions<'a>) -> Result<Self> {
-        let new_id = Self {
-            service: options.service.unwrap_or(self.service),
-            username: options.username.or(self.username),
-            class: options.class.unwrap_or(self.class),
-        };
-        sys::update(self, options)?;
-        Ok(new_id)
+        Keychain::default().update(self, options)
     }
 
     pub fn delete(&self) -> Result<()> {
-        sys::delete(self)
+        Keychain::default().delete(self)
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// An owned version of [`Identifier`].
+///
+/// This can be stored in structs, sent to other threads and used as a map
+/// key.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OwnedIdentifier {
     pub service: String,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub username: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub class: Class,
 }
 
 impl OwnedIdentifier {
-    fn as_ref(&self) -> Identifier {
+    fn as_ref(&self) -> Identifier<'_> {
         Identifier {
             service: self.service.as_ref(),
             username: self.username.as_ref().map(|u| u.as_ref()),
@@ -202,15 +638,88 @@ impl OwnedIdentifier {
     }
 
     /// See the crate-level docs for more information.
-    pub fn load(&self) -> Result<Option<String>> {
+    pub fn load(&self) -> Result<Option<Secret>> {
         self.as_ref().load()
     }
 
-    pub fn update<'a>(&'a self, options: UpdateOptions<'a>) -> Result<Identifier<'a>> {
-        self.as_ref().update(options)
+    /// See [`Identifier::load_bytes`].
+    pub fn load_bytes(&self) -> Result<Option<Secret<Vec<u8>>>> {
+        self.as_ref().load_bytes()
+    }
+
+    /// See [`Identifier::load_json`].
+    #[cfg(feature = "json")]
+    pub fn load_json<T>(&self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.as_ref().load_json()
+    }
+
+    /// See [`Identifier::load_decoded`].
+    #[cfg(feature = "serde")]
+    pub fn load_decoded<C, T>(&self, codec: &C) -> Result<Option<T>>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        self.as_ref().load_decoded(codec)
+    }
+
+    /// See [`Identifier::metadata`].
+    pub fn metadata(&self) -> Result<Option<ItemMetadata>> {
+        self.as_ref().metadata()
+    }
+
+    /// Updates the item, returning its new identifier.
+    pub fn update(&self, options: UpdateOptions) -> Result<OwnedIdentifier> {
+        self.as_ref().update(options).map(|id| id.to_owned())
     }
 
     pub fn delete(&self) -> Result<()> {
         self.as_ref().delete()
     }
 }
+
+impl From<Identifier<'_>> for OwnedIdentifier {
+    fn from(identifier: Identifier) -> Self {
+        identifier.to_owned()
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifier_representation() {
+        let identifier = Identifier {
+            service: "a\"b",
+            username: Some("c\\d"),
+            class: Class::Internet,
+        };
+        let json = serde_json::to_string(&identifier).unwrap();
+        assert_eq!(
+            json,
+            r#"{"service":"a\"b","username":"c\\d","class":"internet"}"#
+        );
+        let owned: OwnedIdentifier = serde_json::from_str(&json).unwrap();
+        assert_eq!(owned, identifier.to_owned());
+        let owned: OwnedIdentifier = serde_json::from_reader(&br#"{"service":"s"}"#[..]).unwrap();
+        assert_eq!((owned.username, owned.class), (None, Class::Generic));
+    }
+
+    #[test]
+    fn update_options_representation() {
+        let options = UpdateOptions::new()
+            .secret("hunter2")
+            .username("new username")
+            .attribute("key", "value");
+        let json = serde_json::to_string(&options).unwrap();
+        assert!(!json.contains("hunter2"));
+        let owned: OwnedUpdateOptions = serde_json::from_str(&json).unwrap();
+        assert!(owned.secret.is_none());
+        assert_eq!(owned.username.as_deref(), Some("new username"));
+        assert_eq!(owned.attributes, [("key".to_owned(), "value".to_owned())]);
+    }
+}
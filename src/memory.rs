@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use crate::{
+    Backend, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier, Result,
+    SearchOptions, Secret, StoreMode, UpdateOptions,
+};
+
+/// A keychain that keeps items in memory.
+///
+/// Items are keyed by the exact service, username and class of their
+/// [`Identifier`], and are lost when the `MemoryKeychain` is dropped. This is
+/// intended for tests and environments without a platform keychain:
+///
+/// ```
+/// use robius_keychain::{Keychain, MemoryKeychain};
+///
+/// Keychain::set_default(Keychain::new(MemoryKeychain::new()));
+/// ```
+///
+/// Requires the `memory` feature.
+#[derive(Debug, Default)]
+pub struct MemoryKeychain {
+    items: Mutex<HashMap<OwnedIdentifier, Item>>,
+}
+
+#[derive(Clone, Debug)]
+struct Item {
+    secret: Secret<Vec<u8>>,
+    label: Option<String>,
+    comment: Option<String>,
+    attributes: BTreeMap<String, String>,
+    created: SystemTime,
+    modified: SystemTime,
+}
+
+impl MemoryKeychain {
+    /// Creates an empty `MemoryKeychain`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Backend for MemoryKeychain {
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()> {
+        let identifier = OwnedIdentifier {
+            service: item.service.to_owned(),
+            username: item.username.map(ToOwned::to_owned),
+            class: item.class,
+        };
+        let mode = item.mode;
+        let now = SystemTime::now();
+        let mut item = Item {
+            secret: Secret::new(item.secret.to_owned()),
+            label: item.label.map(ToOwned::to_owned),
+            comment: item.comment.map(ToOwned::to_owned),
+            attributes: owned_attributes(&item.attributes),
+            created: now,
+            modified: now,
+        };
+        let mut items = self.items.lock().unwrap_or_else(PoisonError::into_inner);
+        match (mode, items.get(&identifier)) {
+            (StoreMode::CreateNew, Some(_)) => return Err(ErrorKind::Duplicate.into()),
+            (StoreMode::Replace, None) => return Err(ErrorKind::NotFound.into()),
+            // A replaced item keeps its creation time.
+            (_, Some(existing)) => item.created = existing.created,
+            _ => {}
+        }
+        items.insert(identifier, item);
+        Ok(())
+    }
+
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&identifier.to_owned())
+            .map(|item| item.secret.clone()))
+    }
+
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&identifier.to_owned())
+            .map(|item| {
+                let mut metadata = ItemMetadata::new();
+                metadata.class = identifier.class;
+                metadata.created = Some(item.created);
+                metadata.modified = Some(item.modified);
+                metadata.label = item.label.clone();
+                metadata.comment = item.comment.clone();
+                metadata.attributes = item.attributes.clone();
+                metadata
+            }))
+    }
+
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        let mut items = self.items.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let identifier = identifier.to_owned();
+        let new_identifier = OwnedIdentifier {
+            service: options.service.unwrap_or(&identifier.service).to_owned(),
+            username: options
+                .username
+                .or(identifier.username.as_deref())
+                .map(ToOwned::to_owned),
+            class: options.class.unwrap_or(identifier.class),
+        };
+        if !items.contains_key(&identifier) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        if new_identifier != identifier && items.contains_key(&new_identifier) {
+            return Err(ErrorKind::Duplicate.into());
+        }
+
+        let mut item = items.remove(&identifier).expect("item exists");
+        if let Some(secret) = options.secret {
+            item.secret = Secret::new(secret.to_owned());
+        }
+        if let Some(label) = options.label {
+            item.label = Some(label.to_owned());
+        }
+        if let Some(comment) = options.comment {
+            item.comment = Some(comment.to_owned());
+        }
+        item.attributes
+            .extend(owned_attributes(&options.attributes));
+        item.modified = SystemTime::now();
+        items.insert(new_identifier, item);
+        Ok(())
+    }
+
+    fn delete(&self, identifier: &Identifier) -> Result<()> {
+        let mut items = self.items.lock().unwrap_or_else(PoisonError::into_inner);
+        match items.remove(&identifier.to_owned()) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|(identifier, item)| {
+                options.matches(&identifier.as_ref())
+                    && options.attributes.iter().all(|&(key, value)| {
+                        item.attributes.get(key).map(AsRef::as_ref) == Some(value)
+                    })
+            })
+            .map(|(identifier, _)| identifier.clone())
+            .collect())
+    }
+}
+
+fn owned_attributes(attributes: &[(&str, &str)]) -> BTreeMap<String, String> {
+    attributes
+        .iter()
+        .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Class, Keychain};
+
+    fn keychain() -> Keychain {
+        Keychain::new(MemoryKeychain::new())
+    }
+
+    #[test]
+    fn contract() {
+        crate::backend::check_backend(&MemoryKeychain::new());
+    }
+
+    #[test]
+    fn search_attributes() {
+        let keychain = keychain();
+        for service in ["mail", "chat"] {
+            keychain
+                .store(KeychainItemBuilder::new(service, "secret").attribute("team", service))
+                .unwrap();
+        }
+
+        let found = keychain
+            .search(&SearchOptions::new().attribute("team", "chat"))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].service, "chat");
+        assert_eq!(
+            keychain
+                .search(&SearchOptions::new().limit(1))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let keychain = keychain();
+        let id = keychain
+            .store(
+                KeychainItemBuilder::new("service", "secret")
+                    .class(Class::Internet)
+                    .label("Label")
+                    .attribute("key", "value"),
+            )
+            .unwrap();
+
+        let metadata = keychain.metadata(&id).unwrap().unwrap();
+        assert_eq!(metadata.class, Class::Internet);
+        assert_eq!(metadata.label.as_deref(), Some("Label"));
+        assert_eq!(metadata.comment, None);
+        assert_eq!(metadata.attributes["key"], "value");
+        assert_eq!(metadata.created, metadata.modified);
+
+        keychain
+            .update(&id, UpdateOptions::new().comment("Comment"))
+            .unwrap();
+        let updated = keychain.metadata(&id).unwrap().unwrap();
+        assert_eq!(updated.comment.as_deref(), Some("Comment"));
+        assert_eq!(updated.label.as_deref(), Some("Label"));
+        assert_eq!(updated.created, metadata.created);
+        assert!(updated.modified >= metadata.modified);
+
+        keychain.delete(&id).unwrap();
+        assert!(keychain.metadata(&id).unwrap().is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
+use crate::Class;
+
+/// Non-secret information about a keychain item.
+///
+/// Returned by [`Identifier::metadata`](crate::Identifier::metadata), which
+/// doesn't load the secret. Fields that aren't supported by a backend are left
+/// empty; see [`KeychainItemBuilder::label`](crate::KeychainItemBuilder::label)
+/// and [`KeychainItemBuilder::comment`](crate::KeychainItemBuilder::comment).
+///
+/// | Platform | Created | Modified |
+/// |----------|---------|----------|
+/// | Android  | Yes     | Yes      |
+/// | Apple    | Yes     | Yes      |
+/// | Linux    | Yes     | Yes      |
+/// | Windows  | No      | Yes      |
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct ItemMetadata {
+    /// The class of the item.
+    pub class: Class,
+    /// When the item was created.
+    pub created: Option<SystemTime>,
+    /// When the item was last modified.
+    pub modified: Option<SystemTime>,
+    /// The human-readable name of the item.
+    pub label: Option<String>,
+    /// A human-readable description of the item.
+    pub comment: Option<String>,
+    /// The custom attributes of the item.
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl ItemMetadata {
+    /// Creates an empty `ItemMetadata`.
+    ///
+    /// The fields can then be set by [`Backend`](crate::Backend)
+    /// implementations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
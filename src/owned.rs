@@ -0,0 +1,305 @@
+use crate::{
+    Class, Keychain, KeychainItemBuilder, OwnedIdentifier, Result, SearchOptions, Secret,
+    StoreMode, UpdateOptions,
+};
+
+/// An owned version of [`KeychainItemBuilder`].
+///
+/// Unlike [`KeychainItemBuilder`], this can be stored in structs and sent to
+/// other threads. The secret is copied into a [`Secret`], so it is zeroed when
+/// the builder is dropped.
+///
+/// ```no_run
+/// use robius_keychain::OwnedKeychainItemBuilder;
+///
+/// let item = OwnedKeychainItemBuilder::new("hello_world", "hunter2").username("username");
+/// let id = std::thread::spawn(move || item.store())
+///     .join()
+///     .unwrap()
+///     .expect("failed to create keychain item");
+/// ```
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OwnedKeychainItemBuilder {
+    pub service: String,
+    pub secret: Secret<Vec<u8>>,
+    pub class: Class,
+    pub username: Option<String>,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub attributes: Vec<(String, String)>,
+    pub mode: StoreMode,
+}
+
+impl OwnedKeychainItemBuilder {
+    /// Creates a new `OwnedKeychainItemBuilder`.
+    ///
+    /// The secret can be a string, a byte slice or a [`Secret`], and is
+    /// copied.
+    pub fn new<S>(service: impl Into<String>, secret: &S) -> Self
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        Self {
+            service: service.into(),
+            secret: Secret::new(secret.as_ref().to_owned()),
+            class: Class::default(),
+            username: None,
+            label: None,
+            comment: None,
+            attributes: Vec::new(),
+            mode: StoreMode::default(),
+        }
+    }
+
+    /// Sets the class for the keychain item.
+    pub fn class(mut self, class: Class) -> Self {
+        self.class = class;
+        self
+    }
+
+    /// Sets the username for the keychain item.
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Sets the label for the keychain item.
+    ///
+    /// See [`KeychainItemBuilder::label`].
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Sets the comment for the keychain item.
+    ///
+    /// See [`KeychainItemBuilder::comment`].
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Adds a custom attribute to the keychain item.
+    ///
+    /// See [`KeychainItemBuilder::attribute`].
+    pub fn attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets how to store the item if it already exists.
+    ///
+    /// See [`KeychainItemBuilder::mode`].
+    pub fn mode(mut self, mode: StoreMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Borrows the builder as a [`KeychainItemBuilder`].
+    pub fn as_builder(&self) -> KeychainItemBuilder<'_> {
+        KeychainItemBuilder {
+            service: &self.service,
+            secret: self.secret.expose(),
+            class: self.class,
+            username: self.username.as_deref(),
+            label: self.label.as_deref(),
+            comment: self.comment.as_deref(),
+            attributes: borrow_attributes(&self.attributes),
+            mode: self.mode,
+        }
+    }
+
+    /// Stores the item in the default keychain.
+    ///
+    /// See [`KeychainItemBuilder::store`].
+    pub fn store(&self) -> Result<OwnedIdentifier> {
+        Keychain::default()
+            .store(self.as_builder())
+            .map(|id| id.to_owned())
+    }
+}
+
+impl From<&KeychainItemBuilder<'_>> for OwnedKeychainItemBuilder {
+    fn from(item: &KeychainItemBuilder) -> Self {
+        Self {
+            service: item.service.to_owned(),
+            secret: Secret::new(item.secret.to_owned()),
+            class: item.class,
+            username: item.username.map(ToOwned::to_owned),
+            label: item.label.map(ToOwned::to_owned),
+            comment: item.comment.map(ToOwned::to_owned),
+            attributes: own_attributes(&item.attributes),
+            mode: item.mode,
+        }
+    }
+}
+
+impl From<KeychainItemBuilder<'_>> for OwnedKeychainItemBuilder {
+    fn from(item: KeychainItemBuilder) -> Self {
+        Self::from(&item)
+    }
+}
+
+/// An owned version of [`UpdateOptions`].
+///
+/// Unlike [`UpdateOptions`], this can be stored in structs and sent to other
+/// threads.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct OwnedUpdateOptions {
+    pub service: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub secret: Option<Secret<Vec<u8>>>,
+    pub class: Option<Class>,
+    pub username: Option<String>,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl OwnedUpdateOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the service for the keychain item.
+    pub fn service(mut self, service: impl Into<String>) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Sets the secret for the keychain item.
+    ///
+    /// The secret can be a string, a byte slice or a [`Secret`], and is
+    /// copied.
+    pub fn secret<S>(mut self, secret: &S) -> Self
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        self.secret = Some(Secret::new(secret.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the class for the keychain item.
+    pub fn class(mut self, class: Class) -> Self {
+        self.class = Some(class);
+        self
+    }
+
+    /// Sets the username for the keychain item.
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    /// Sets the label for the keychain item.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Sets the comment for the keychain item.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Sets a custom attribute on the keychain item.
+    ///
+    /// See [`UpdateOptions::attribute`].
+    pub fn attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.push((key.into(), value.into()));
+        self
+    }
+
+    /// Borrows the options as [`UpdateOptions`].
+    pub fn as_options(&self) -> UpdateOptions<'_> {
+        UpdateOptions {
+            service: self.service.as_deref(),
+            secret: self
+                .secret
+                .as_ref()
+                .map(|secret| secret.expose().as_slice()),
+            class: self.class,
+            username: self.username.as_deref(),
+            label: self.label.as_deref(),
+            comment: self.comment.as_deref(),
+            attributes: borrow_attributes(&self.attributes),
+        }
+    }
+}
+
+impl From<&UpdateOptions<'_>> for OwnedUpdateOptions {
+    fn from(options: &UpdateOptions) -> Self {
+        Self {
+            service: options.service.map(ToOwned::to_owned),
+            secret: options.secret.map(|secret| Secret::new(secret.to_owned())),
+            class: options.class,
+            username: options.username.map(ToOwned::to_owned),
+            label: options.label.map(ToOwned::to_owned),
+            comment: options.comment.map(ToOwned::to_owned),
+            attributes: own_attributes(&options.attributes),
+        }
+    }
+}
+
+impl From<UpdateOptions<'_>> for OwnedUpdateOptions {
+    fn from(options: UpdateOptions) -> Self {
+        Self::from(&options)
+    }
+}
+
+/// An owned version of [`SearchOptions`], used to move the options to a
+/// worker thread.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OwnedSearchOptions {
+    service: Option<String>,
+    username: Option<String>,
+    username_prefix: Option<String>,
+    class: Option<Class>,
+    attributes: Vec<(String, String)>,
+    limit: Option<usize>,
+}
+
+impl OwnedSearchOptions {
+    pub(crate) fn as_options(&self) -> SearchOptions<'_> {
+        SearchOptions {
+            service: self.service.as_deref(),
+            username: self.username.as_deref(),
+            username_prefix: self.username_prefix.as_deref(),
+            class: self.class,
+            attributes: borrow_attributes(&self.attributes),
+            limit: self.limit,
+        }
+    }
+}
+
+impl From<&SearchOptions<'_>> for OwnedSearchOptions {
+    fn from(options: &SearchOptions) -> Self {
+        Self {
+            service: options.service.map(ToOwned::to_owned),
+            username: options.username.map(ToOwned::to_owned),
+            username_prefix: options.username_prefix.map(ToOwned::to_owned),
+            class: options.class,
+            attributes: own_attributes(&options.attributes),
+            limit: options.limit,
+        }
+    }
+}
+
+fn own_attributes(attributes: &[(&str, &str)]) -> Vec<(String, String)> {
+    attributes
+        .iter()
+        .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn borrow_attributes(attributes: &[(String, String)]) -> Vec<(&str, &str)> {
+    attributes
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
@@ -0,0 +1,85 @@
+use std::{fmt, mem, str::Utf8Error};
+
+use zeroize::Zeroize;
+
+/// Secret material, such as a password or key.
+///
+/// The contents are only accessible through [`Secret::expose`], are redacted
+/// from the `Debug` and `Display` output, and are overwritten with zeroes when
+/// the `Secret` is dropped.
+///
+/// A `Secret` can be passed to [`KeychainItemBuilder::new`] and
+/// [`UpdateOptions::secret`].
+///
+/// [`KeychainItemBuilder::new`]: crate::KeychainItemBuilder::new
+/// [`UpdateOptions::secret`]: crate::UpdateOptions::secret
+#[derive(Clone, Default)]
+pub struct Secret<T: Zeroize = String>(T);
+
+impl<T: Zeroize> Secret<T> {
+    /// Wraps a value in a `Secret`.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns a reference to the secret material.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Returns a mutable reference to the secret material, so that it can be
+    /// filled in place without leaving copies behind.
+    #[cfg_attr(
+        not(any(
+            feature = "file",
+            target_os = "android",
+            all(target_os = "linux", feature = "secret-service"),
+            all(target_os = "linux", feature = "keyutils")
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl Secret<Vec<u8>> {
+    /// Converts a binary secret to a string secret without copying it.
+    pub(crate) fn into_string(mut self) -> Result<Secret<String>, Utf8Error> {
+        std::str::from_utf8(&self.0)?;
+        let bytes = mem::take(&mut self.0);
+        Ok(Secret(
+            String::from_utf8(bytes).expect("secret was checked to be valid UTF-8"),
+        ))
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> AsRef<[u8]> for Secret<T> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
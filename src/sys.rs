@@ -1,18 +1,92 @@
+use crate::{
+    Backend, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier, Result, SearchOptions,
+    Secret, UpdateOptions,
+};
+
 cfg_if::cfg_if! {
     if #[cfg(target_os = "android")] {
         mod android;
-        pub(crate) use android::*;
+        use android as imp;
     } else if #[cfg(target_vendor = "apple")] {
         mod apple;
-        pub(crate) use apple::*;
+        use apple as imp;
     } else if #[cfg(target_os = "linux")] {
-        mod linux;
-        pub(crate) use linux::*;
+        #[cfg(any(feature = "libsecret", feature = "secret-service"))]
+        mod schema;
+        #[cfg(feature = "secret-service")]
+        mod secret_service;
+        #[cfg(feature = "secret-service")]
+        pub use secret_service::{SecretService, SecretServiceBuilder, SessionEncryption};
+        #[cfg(feature = "keyutils")]
+        mod keyutils;
+        #[cfg(feature = "keyutils")]
+        pub use keyutils::{KernelKeyring, KernelKeyringBuilder, KeyringScope};
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "libsecret")] {
+                mod linux;
+                use linux as imp;
+            } else if #[cfg(feature = "secret-service")] {
+                use secret_service::platform as imp;
+            } else {
+                mod unsupported;
+                use unsupported as imp;
+            }
+        }
     } else if #[cfg(target_os = "windows")] {
         mod windows;
-        pub(crate) use windows::*;
+        use windows as imp;
     } else {
         mod unsupported;
-        pub(crate) use unsupported::*;
+        use unsupported as imp;
+    }
+}
+
+/// The keychain provided by the operating system.
+///
+/// | Platform | Store                                        |
+/// |----------|----------------------------------------------|
+/// | Android  | Encrypted files in the app's data directory  |
+/// | Apple    | Keychain Services                            |
+/// | Linux    | Secret Service (through libsecret or D-Bus)  |
+/// | Windows  | Credential Manager                           |
+///
+/// On Android, items are stored by a `FileKeychain` with its master key in a
+/// `KeyFile`. Items stored in plaintext by earlier versions are encrypted and
+/// their plaintext files removed when they are first accessed.
+///
+/// On Linux, the Secret Service is accessed through libsecret with the default
+/// `libsecret` feature, and otherwise through `SecretService` with the
+/// `secret-service` feature. Both encrypt secrets on the bus if the service
+/// supports it, but only a `SecretService` built with
+/// `SessionEncryption::Required` refuses to send them in cleartext. Where no
+/// Secret Service runs, a `KernelKeyring` can be used instead with the
+/// `keyutils` feature.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Platform;
+
+impl Backend for Platform {
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()> {
+        imp::store(item)
+    }
+
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        imp::load(identifier)
+    }
+
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        imp::metadata(identifier)
+    }
+
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        imp::update(identifier, options)
+    }
+
+    fn delete(&self, identifier: &Identifier) -> Result<()> {
+        imp::delete(identifier)
+    }
+
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        imp::search(options)
     }
 }
@@ -1,88 +1,174 @@
-use std::{io, path::PathBuf};
-
-use robius_directories::ProjectDirs;
-
-use crate::{error::ErrorVariant, Error, Identifier, KeychainItemBuilder, Result, UpdateOptions};
-
-const SEPARATOR: char = '\x1f';
-
-pub fn store(item: KeychainItemBuilder) -> Result<()> {
-    std::fs::create_dir_all(dir()?)?;
-
-    let identifier = Identifier {
-        service: item.service,
-        class: item.class,
-        username: item.username,
-    };
-
-    std::fs::write(file(&identifier)?, item.secret)?;
-    Ok(())
+use std::{fs, io, sync::OnceLock};
+
+use crate::{
+    file::{self, FileKeychain},
+    Backend, Class, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder,
+    OwnedIdentifier, Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
+
+/// Separates the username from the service in the names of legacy files.
+const LEGACY_SEPARATOR: char = '\x1f';
+
+pub fn store(item: &KeychainItemBuilder) -> Result<()> {
+    let keychain = keychain()?;
+    migrate(
+        keychain,
+        &Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        },
+    )?;
+    keychain.store(item)
 }
 
-pub fn load(identifier: &Identifier) -> Result<Option<String>> {
-    let bytes = match std::fs::read(file(identifier)?) {
-        Ok(bytes) => bytes,
-        Err(error) => {
-            return match error.kind() {
-                io::ErrorKind::NotFound => Ok(None),
-                _ => Err(Error::from(error)),
-            }
-        }
-    };
+pub fn load(identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+    let keychain = keychain()?;
+    match keychain.load(identifier)? {
+        None if migrate(keychain, identifier)? => keychain.load(identifier),
+        secret => Ok(secret),
+    }
+}
 
-    match String::from_utf8(bytes) {
-        Ok(string) => Ok(Some(string)),
-        Err(error) => Err(Error::from(error)),
+pub fn metadata(identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+    let keychain = keychain()?;
+    match keychain.metadata(identifier)? {
+        None if migrate(keychain, identifier)? => keychain.metadata(identifier),
+        metadata => Ok(metadata),
     }
 }
 
-pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
-    let new_identifier = Identifier {
-        service: options.service.unwrap_or(identifier.service),
-        class: options.class.unwrap_or(identifier.class),
-        username: match options.username {
-            Some(username) => Some(username),
-            None => identifier.username,
+pub fn update(identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+    let keychain = keychain()?;
+    migrate(keychain, identifier)?;
+    // A legacy item under the new identifier is migrated too, so that the
+    // update fails as a duplicate instead of leaving it behind.
+    migrate(
+        keychain,
+        &Identifier {
+            service: options.service.unwrap_or(identifier.service),
+            username: options.username.or(identifier.username),
+            class: options.class.unwrap_or(identifier.class),
         },
-    };
+    )?;
+    keychain.update(identifier, options)
+}
 
-    let old_path = file(identifier)?;
-    let new_path = file(&new_identifier)?;
+pub fn delete(identifier: &Identifier) -> Result<()> {
+    let keychain = keychain()?;
+    migrate(keychain, identifier)?;
+    keychain.delete(identifier)
+}
 
-    if old_path != new_path {
-        std::fs::rename(old_path, &new_path)?;
+pub fn search(options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    let keychain = keychain()?;
+    let mut identifiers = keychain.search(options)?;
+    // Legacy items have no attributes.
+    if options.attributes.is_empty() {
+        identifiers.extend(legacy_items(keychain)?);
     }
+    Ok(identifiers)
+}
 
-    if let Some(secret) = options.secret {
-        std::fs::write(new_path, secret)?;
-    }
+/// Returns the keychain in the app's data directory.
+fn keychain() -> Result<&'static FileKeychain> {
+    static KEYCHAIN: OnceLock<FileKeychain> = OnceLock::new();
 
-    Ok(())
+    if let Some(keychain) = KEYCHAIN.get() {
+        return Ok(keychain);
+    }
+    let keychain = FileKeychain::builder().build()?;
+    Ok(KEYCHAIN.get_or_init(|| keychain))
 }
 
-pub fn delete(identifier: &Identifier) -> Result<()> {
-    std::fs::remove_file(file(identifier)?).map_err(Error::from)
+/// Returns the name of the file in which earlier versions stored the secret of
+/// an item in plaintext.
+///
+/// These files are in the same directory as the keychain. The class wasn't
+/// part of the name, so both classes share a file.
+fn legacy_file_name(identifier: &Identifier) -> String {
+    match identifier.username {
+        Some(username) => format!("{username}{LEGACY_SEPARATOR}{}", identifier.service),
+        None => identifier.service.to_owned(),
+    }
 }
 
-fn file(identifier: &Identifier) -> Result<PathBuf> {
-    dir().map(|dir| dir.join(file_name(identifier)))
+/// Reads a legacy file, returning `None` if it doesn't exist or is a file of
+/// the keychain itself.
+fn read_legacy_file(keychain: &FileKeychain, name: &str) -> Result<Option<Vec<u8>>> {
+    if file::is_reserved_name(name) {
+        return Ok(None);
+    }
+    let path = keychain.dir().join(name);
+    match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(_) => return Ok(None),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    let contents = fs::read(&path)?;
+    Ok((!file::is_item_file(&contents)).then_some(contents))
 }
 
-fn file_name(identifier: &Identifier) -> String {
-    if let Some(username) = identifier.username {
-        // TODO: Do we bother documenting this. It's kind of an internal implementation
-        // detail. Note that the username and service can contain \x1f.
-        //
-        // TODO: Would we rather use a printable character?
-        format!("{}{SEPARATOR}{}", username, identifier.service)
-    } else {
-        identifier.service.to_owned()
+/// Moves an item stored in plaintext by an earlier version into the keychain,
+/// returning whether there was one.
+fn migrate(keychain: &FileKeychain, identifier: &Identifier) -> Result<bool> {
+    let name = legacy_file_name(identifier);
+    let Some(contents) = read_legacy_file(keychain, &name)? else {
+        return Ok(false);
+    };
+    let secret = Secret::new(contents);
+    let mut item = KeychainItemBuilder::new(identifier.service, secret.expose());
+    item.username = identifier.username;
+    item.class = identifier.class;
+    item.mode = StoreMode::CreateNew;
+    match keychain.store(&item) {
+        // The item was migrated concurrently, or stored again since.
+        Err(error) if error.kind() == ErrorKind::Duplicate => {}
+        result => result?,
+    }
+    match fs::remove_file(keychain.dir().join(name)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::from(error)),
+        _ => Ok(true),
     }
 }
 
-fn dir() -> Result<PathBuf> {
-    Ok(ProjectDirs::from("", "", "")
-        .ok_or(Error(ErrorVariant::Directories))?
-        .data_dir()
-        .join("robius-keychain"))
+/// Returns the identifiers of items stored in plaintext by earlier versions.
+///
+/// The names of legacy files don't record the class, and the separator may
+/// also appear in the username or service, so the returned identifiers may
+/// differ from those the items were stored with. They still refer to the same
+/// files, which are migrated when the items are accessed.
+fn legacy_items(keychain: &FileKeychain) -> Result<Vec<OwnedIdentifier>> {
+    let entries = match fs::read_dir(keychain.dir()) {
+        Ok(entries) => entries,
+        Err(error) => {
+            return match error.kind() {
+                io::ErrorKind::NotFound => Ok(Vec::new()),
+                _ => Err(Error::from(error)),
+            }
+        }
+    };
+
+    let mut identifiers = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        // Legacy names were created from strings.
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if read_legacy_file(keychain, &name)?.is_none() {
+            continue;
+        }
+        let (username, service) = match name.split_once(LEGACY_SEPARATOR) {
+            Some((username, service)) => (Some(username.to_owned()), service.to_owned()),
+            None => (None, name),
+        };
+        identifiers.push(OwnedIdentifier {
+            service,
+            username,
+            class: Class::Generic,
+        });
+    }
+    Ok(identifiers)
 }
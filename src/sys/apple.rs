@@ -1,45 +1,124 @@
-use core_foundation::data::CFData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use core_foundation::{
+    base::{CFType, TCFType},
+    data::CFData,
+    date::CFDate,
+    dictionary::CFDictionary,
+    string::CFString,
+};
 use security_framework::item::{
     update_item, ItemAddOptions, ItemAddValue, ItemClass, ItemSearchOptions, ItemUpdateOptions,
-    ItemUpdateValue, SearchResult,
+    ItemUpdateValue, Limit, SearchResult,
 };
 
-use crate::{Class, Error, Identifier, KeychainItemBuilder, Result, UpdateOptions};
+use crate::{
+    Class, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier,
+    Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
 
-pub fn store(item: KeychainItemBuilder) -> Result<()> {
+pub fn store(item: &KeychainItemBuilder) -> Result<()> {
+    check_no_attributes(&item.attributes)?;
     let mut options = ItemAddOptions::new(ItemAddValue::Data {
         class: convert_class(item.class),
-        data: CFData::from_buffer(item.secret.as_ref()),
+        data: CFData::from_buffer(item.secret),
     });
     if let Some(username) = item.username {
         options.set_account_name(username);
     }
     options.set_service(item.service);
+    if let Some(label) = item.label {
+        options.set_label(label);
+    }
+    if let Some(comment) = item.comment {
+        options.set_comment(comment);
+    }
 
-    options.add().map_err(Error)
+    // Keychain Services can't add over an existing item, so it is updated in
+    // place instead, which is never missing in between.
+    match item.mode {
+        StoreMode::CreateNew => options.add().map_err(Error::from),
+        StoreMode::Replace => replace(item),
+        StoreMode::Upsert => match options.add().map_err(Error::from) {
+            Err(error) if error.kind() == ErrorKind::Duplicate => replace(item),
+            result => result,
+        },
+    }
 }
 
-pub fn load(identifier: &Identifier) -> Result<Option<String>> {
-    Ok(search_options(identifier)
-        .load_data(true)
-        .search()
-        .map_err(Error)?
-        .into_iter()
-        .next()
-        .and_then(|r| match r {
-            SearchResult::Data(d) => String::from_utf8(d).ok(),
-            // TODO: Log error?
-            _ => None,
-        }))
+/// Replaces the secret, label and comment of an existing item.
+fn replace(item: &KeychainItemBuilder) -> Result<()> {
+    let identifier = Identifier {
+        service: item.service,
+        username: item.username,
+        class: item.class,
+    };
+    let mut options = ItemUpdateOptions::new();
+    options.set_value(ItemUpdateValue::Data(CFData::from_buffer(item.secret)));
+    // Fields that aren't set are reset, as if the item was added anew. Keychain
+    // Services labels new generic passwords with their service.
+    options.set_label(item.label.unwrap_or(item.service));
+    options.set_comment(item.comment.unwrap_or(""));
+    with_item(&identifier, |search| {
+        update_item(&search, &options).map_err(Error::from)
+    })
 }
 
-pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
+pub fn load(identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+    let results = match with_item(identifier, |mut search| {
+        Ok(search.load_data(true).search()?)
+    }) {
+        Ok(results) => results,
+        Err(error) => {
+            return match error.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            };
+        }
+    };
+
+    Ok(results.into_iter().next().and_then(|r| match r {
+        SearchResult::Data(d) => Some(Secret::new(d)),
+        // TODO: Log error?
+        _ => None,
+    }))
+}
+
+pub fn metadata(identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+    let results = match with_item(identifier, |mut search| {
+        Ok(search.load_attributes(true).search()?)
+    }) {
+        Ok(results) => results,
+        Err(error) => {
+            return match error.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            };
+        }
+    };
+
+    Ok(results.into_iter().next().map(|result| {
+        let mut attributes = result.simplify_dict().unwrap_or_default();
+        let mut metadata = ItemMetadata::new();
+        metadata.class = identifier.class;
+        if let SearchResult::Dict(ref dict) = result {
+            metadata.created = date(dict, "cdat");
+            metadata.modified = date(dict, "mdat");
+        }
+        metadata.label = attributes.remove("labl");
+        metadata.comment = attributes.remove("icmt");
+        metadata
+    }))
+}
+
+pub fn update(identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+    check_no_attributes(&options.attributes)?;
     let mut sf_options = ItemUpdateOptions::new();
     if let Some(service) = options.service {
         sf_options.set_service(service);
     }
     if let Some(secret) = options.secret {
-        sf_options.set_value(ItemUpdateValue::Data(CFData::from_buffer(secret.as_ref())));
+        sf_options.set_value(ItemUpdateValue::Data(CFData::from_buffer(secret)));
     }
     if let Some(class) = options.class {
         sf_options.set_class(convert_class(class));
@@ -47,27 +126,186 @@ pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
     if let Some(username) = options.username {
         sf_options.set_account_name(username);
     }
-    update_item(&search_options(identifier), &sf_options).map_err(Error)
+    if let Some(label) = options.label {
+        sf_options.set_label(label);
+    }
+    if let Some(comment) = options.comment {
+        sf_options.set_comment(comment);
+    }
+    with_item(identifier, |search| {
+        update_item(&search, &sf_options).map_err(Error::from)
+    })
 }
 
 pub fn delete(identifier: &Identifier) -> Result<()> {
-    search_options(identifier).delete().map_err(Error)
+    with_item(identifier, |search| search.delete().map_err(Error::from))
+}
+
+pub fn search(options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    check_no_attributes(&options.attributes)?;
+    let classes = match options.class {
+        Some(class) => vec![class],
+        None => vec![Class::Generic, Class::Internet],
+    };
+
+    let mut identifiers = Vec::new();
+    for class in classes {
+        let mut sf_options = ItemSearchOptions::new();
+        sf_options
+            .class(convert_class(class))
+            .load_attributes(true)
+            .limit(Limit::All);
+        if let Some(service) = options.service {
+            sf_options.service(service);
+        }
+        if let Some(username) = options.username {
+            sf_options.account(username);
+        }
+
+        let results = match sf_options.search() {
+            Ok(results) => results,
+            Err(error) => {
+                let error = Error::from(error);
+                match error.kind() {
+                    ErrorKind::NotFound => continue,
+                    _ => return Err(error),
+                }
+            }
+        };
+
+        for result in results {
+            let Some(mut attributes) = result.simplify_dict() else {
+                continue;
+            };
+            let Some(service) = attributes.remove("svce") else {
+                continue;
+            };
+            identifiers.push(OwnedIdentifier {
+                service,
+                username: attributes.remove("acct").filter(|u| !u.is_empty()),
+                class,
+            });
+        }
+    }
+    Ok(identifiers)
 }
 
 fn search_options(identifier: &Identifier) -> ItemSearchOptions {
     let mut options = ItemSearchOptions::new();
+    // Items without a username have an empty account. Leaving the account out
+    // would match the items of every username instead.
     options
         .service(identifier.service)
+        .account(identifier.username.unwrap_or(""))
         .class(convert_class(identifier.class));
-    if let Some(username) = identifier.username {
-        options.account(username);
-    }
     options
 }
 
+/// Runs `f` with the options to search for an item, falling back to those for
+/// an item stored without an account if it isn't found.
+fn with_item<T>(identifier: &Identifier, f: impl Fn(ItemSearchOptions) -> Result<T>) -> Result<T> {
+    match f(search_options(identifier)) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            match legacy_search_options(identifier)? {
+                Some(options) => f(options),
+                None => Err(error),
+            }
+        }
+        result => result,
+    }
+}
+
+/// Returns the options to search for an item without a username that was
+/// stored by an earlier version, which didn't set an account, or `None` if
+/// there is no such item.
+///
+/// Leaving the account out also matches the items of every username, so the
+/// options are only returned if they match none of those.
+fn legacy_search_options(identifier: &Identifier) -> Result<Option<ItemSearchOptions>> {
+    if identifier.username.is_some() {
+        return Ok(None);
+    }
+    let options = || {
+        let mut options = ItemSearchOptions::new();
+        options
+            .service(identifier.service)
+            .class(convert_class(identifier.class));
+        options
+    };
+
+    let results = match options().load_attributes(true).limit(Limit::All).search() {
+        Ok(results) => results,
+        Err(error) => {
+            let error = Error::from(error);
+            return match error.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            };
+        }
+    };
+    let accountless = results.iter().all(|result| {
+        result
+            .simplify_dict()
+            .is_some_and(|attributes| attributes.get("acct").is_none_or(|a| a.is_empty()))
+    });
+    Ok(accountless.then(options))
+}
+
+// Keychain Services has a fixed set of attributes, none of which are suitable
+// for arbitrary key-value pairs.
+fn check_no_attributes(attributes: &[(&str, &str)]) -> Result<()> {
+    if attributes.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "custom attributes are not supported by the Apple keychain",
+        ))
+    }
+}
+
+fn date(dict: &CFDictionary, key: &'static str) -> Option<SystemTime> {
+    // The number of seconds between 1970-01-01 and the Core Foundation epoch,
+    // 2001-01-01.
+    const ABSOLUTE_TIME_INTERVAL_SINCE_1970: f64 = 978_307_200.0;
+
+    let key = CFString::from_static_string(key);
+    let value = dict.find(key.as_CFTypeRef())?;
+    // SAFETY: The values of a search result dictionary are Core Foundation
+    // objects.
+    let value = unsafe { CFType::wrap_under_get_rule(*value) };
+    let seconds = value.downcast::<CFDate>()?.abs_time() + ABSOLUTE_TIME_INTERVAL_SINCE_1970;
+    UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+}
+
 fn convert_class(class: Class) -> ItemClass {
     match class {
         Class::Generic => ItemClass::generic_password(),
         Class::Internet => ItemClass::internet_password(),
     }
 }
+
+impl From<security_framework::base::Error> for Error {
+    fn from(error: security_framework::base::Error) -> Self {
+        let kind = match error.code() {
+            // errSecItemNotFound
+            -25300 => ErrorKind::NotFound,
+            // errSecDuplicateItem
+            -25299 => ErrorKind::Duplicate,
+            // errSecAuthFailed, errSecMissingEntitlement
+            -25293 | -34018 => ErrorKind::AccessDenied,
+            // errSecInteractionNotAllowed
+            -25308 => ErrorKind::Locked,
+            // errSecUserCanceled
+            -128 => ErrorKind::Cancelled,
+            // errSecNotAvailable, errSecNoSuchKeychain
+            -25291 | -25294 => ErrorKind::Unavailable,
+            // errSecDecode
+            -26275 => ErrorKind::InvalidData,
+            // errSecUnimplemented
+            -4 => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        };
+        Error::new(kind, error)
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    ffi::{c_char, c_long, c_ulong, CString},
+    io,
+    time::Duration,
+};
+
+use crate::{
+    Backend, Class, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder,
+    OwnedIdentifier, Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
+
+const DEFAULT_NAMESPACE: &str = "robius-keychain";
+
+/// The type of the keys holding items, whose payload is the secret.
+const KEY_TYPE: &[u8] = b"user\0";
+
+/// All permissions for possessors and the owner of a key, and none for
+/// others. New keys can only be viewed by their owner unless possessed.
+const PERMISSIONS: c_ulong = 0x3f3f_0000;
+
+/// The keyring in which a [`KernelKeyring`] stores its items.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum KeyringScope {
+    /// The user keyring, shared by all processes of the user. Its keys are
+    /// kept until the last process of the user exits.
+    #[default]
+    User,
+    /// The session keyring, shared by the processes of the login session.
+    Session,
+    /// The persistent keyring of the user, which outlives login sessions
+    /// and expires after it hasn't been used for a few days.
+    Persistent,
+    /// The process keyring, private to the current process.
+    Process,
+}
+
+/// A keychain backend storing items in the Linux kernel key retention
+/// service.
+///
+/// This is meant for headless servers and containers without a Secret
+/// Service. Each item is a `user` key in the keyring of the [`KeyringScope`],
+/// described as `namespace:class:service:username`, where `:` and `%` are
+/// percent-encoded in the service and username. The keys can be inspected
+/// with `keyctl show`.
+///
+/// Kernel keys are kept in memory only, so they don't survive a reboot.
+/// Labels and comments are not stored, and custom attributes are not
+/// supported.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use robius_keychain::{KernelKeyring, Keychain, KeyringScope};
+///
+/// let keyring = KernelKeyring::builder()
+///     .scope(KeyringScope::Persistent)
+///     .timeout(Duration::from_secs(60 * 60))
+///     .build()
+///     .expect("invalid namespace");
+/// Keychain::set_default(Keychain::new(keyring));
+/// ```
+#[derive(Clone, Debug)]
+pub struct KernelKeyring {
+    scope: KeyringScope,
+    namespace: String,
+    timeout: Option<Duration>,
+}
+
+impl KernelKeyring {
+    /// Creates a keychain in the keyring of the given scope, with the
+    /// default namespace and without a timeout.
+    pub fn new(scope: KeyringScope) -> Self {
+        Self {
+            scope,
+            namespace: DEFAULT_NAMESPACE.to_owned(),
+            timeout: None,
+        }
+    }
+
+    /// Returns a builder to configure the keychain.
+    pub fn builder() -> KernelKeyringBuilder {
+        KernelKeyringBuilder::default()
+    }
+
+    /// Returns the scope of the keyring holding the items.
+    pub fn scope(&self) -> KeyringScope {
+        self.scope
+    }
+
+    /// Returns the serial number of the keyring holding the items.
+    fn keyring(&self) -> Result<c_long> {
+        match self.scope {
+            KeyringScope::User => Ok(libc::KEY_SPEC_USER_KEYRING as c_long),
+            KeyringScope::Session => Ok(libc::KEY_SPEC_SESSION_KEYRING as c_long),
+            KeyringScope::Process => Ok(libc::KEY_SPEC_PROCESS_KEYRING as c_long),
+            // The persistent keyring is linked into the process keyring, so
+            // that it is possessed and its expiry is reset.
+            KeyringScope::Persistent => keyctl(
+                libc::KEYCTL_GET_PERSISTENT,
+                [
+                    libc::uid_t::MAX as c_ulong,
+                    libc::KEY_SPEC_PROCESS_KEYRING as c_ulong,
+                    0,
+                    0,
+                ],
+            ),
+        }
+    }
+
+    /// Returns the description of the key holding an item.
+    fn description(&self, identifier: &Identifier) -> Result<CString> {
+        let mut description = format!(
+            "{}:{}:{}",
+            self.namespace,
+            class_name(identifier.class),
+            escape(identifier.service)
+        );
+        if let Some(username) = identifier.username {
+            description.push(':');
+            description.push_str(&escape(username));
+        }
+        CString::new(description).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Parses the description of a key, if it holds an item of this
+    /// keychain.
+    fn parse(&self, description: &str) -> Option<OwnedIdentifier> {
+        let mut fields = description
+            .strip_prefix(&self.namespace)?
+            .strip_prefix(':')?
+            .split(':');
+        let class = match fields.next()? {
+            "generic" => Class::Generic,
+            "internet" => Class::Internet,
+            _ => return None,
+        };
+        let service = unescape(fields.next()?)?;
+        let username = fields
+            .next()
+            .map(unescape)
+            .map_or(Some(None), |u| u.map(Some))?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(OwnedIdentifier {
+            service,
+            username,
+            class,
+        })
+    }
+
+    /// Adds or updates a key, and sets its permissions and expiry.
+    fn add(&self, keyring: c_long, description: &CString, secret: &[u8]) -> Result<()> {
+        // SAFETY: The type and description are NUL-terminated, and the
+        // payload is valid for `secret.len()` bytes.
+        let key = check(unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                KEY_TYPE.as_ptr() as *const c_char,
+                description.as_ptr(),
+                secret.as_ptr(),
+                secret.len(),
+                keyring,
+            )
+        })?;
+        keyctl(libc::KEYCTL_SETPERM, [key as c_ulong, PERMISSIONS, 0, 0])?;
+        self.set_timeout(key)
+    }
+
+    fn set_timeout(&self, key: c_long) -> Result<()> {
+        let Some(timeout) = self.timeout else {
+            return Ok(());
+        };
+        // A timeout of zero would clear the expiry.
+        let seconds = timeout
+            .as_secs()
+            .saturating_add(u64::from(timeout.subsec_nanos() > 0))
+            .max(1);
+        keyctl(
+            libc::KEYCTL_SET_TIMEOUT,
+            [
+                key as c_ulong,
+                seconds.min(u32::MAX as u64) as c_ulong,
+                0,
+                0,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Backend for KernelKeyring {
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()> {
+        check_no_attributes(&item.attributes)?;
+        let keyring = self.keyring()?;
+        let description = self.description(&Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        })?;
+
+        // `add_key` always replaces an existing key.
+        if item.mode != StoreMode::Upsert {
+            let exists = find(keyring, &description)?.is_some();
+            match (item.mode, exists) {
+                (StoreMode::CreateNew, true) => return Err(ErrorKind::Duplicate.into()),
+                (StoreMode::Replace, false) => return Err(ErrorKind::NotFound.into()),
+                _ => {}
+            }
+        }
+        self.add(keyring, &description, item.secret)
+    }
+
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        match find(self.keyring()?, &self.description(identifier)?)? {
+            Some(key) => read(libc::KEYCTL_READ, key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Kernel keys have no timestamps, labels or comments, so only the class
+    /// is set.
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        Ok(
+            find(self.keyring()?, &self.description(identifier)?)?.map(|_| {
+                let mut metadata = ItemMetadata::new();
+                metadata.class = identifier.class;
+                metadata
+            }),
+        )
+    }
+
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        check_no_attributes(&options.attributes)?;
+        let keyring = self.keyring()?;
+        let description = self.description(identifier)?;
+        let key = find(keyring, &description)?.ok_or(Error::from(ErrorKind::NotFound))?;
+
+        let new_description = self.description(&Identifier {
+            service: options.service.unwrap_or(identifier.service),
+            username: options.username.or(identifier.username),
+            class: options.class.unwrap_or(identifier.class),
+        })?;
+        if new_description == description {
+            if let Some(secret) = options.secret {
+                keyctl(
+                    libc::KEYCTL_UPDATE,
+                    [
+                        key as c_ulong,
+                        secret.as_ptr() as c_ulong,
+                        secret.len() as c_ulong,
+                        0,
+                    ],
+                )?;
+                self.set_timeout(key)?;
+            }
+            return Ok(());
+        }
+
+        // `add_key` would replace the key of another item.
+        if find(keyring, &new_description)?.is_some() {
+            return Err(ErrorKind::Duplicate.into());
+        }
+        // Keys can't be renamed, so the new key is added before the old one
+        // is removed.
+        let loaded;
+        let secret = match options.secret {
+            Some(secret) => secret,
+            None => {
+                loaded = read(libc::KEYCTL_READ, key)?;
+                loaded.expose().as_slice()
+            }
+        };
+        self.add(keyring, &new_description, secret)?;
+        keyctl(libc::KEYCTL_INVALIDATE, [key as c_ulong, 0, 0, 0])?;
+        Ok(())
+    }
+
+    fn delete(&self, identifier: &Identifier) -> Result<()> {
+        let key = find(self.keyring()?, &self.description(identifier)?)?
+            .ok_or(Error::from(ErrorKind::NotFound))?;
+        keyctl(libc::KEYCTL_INVALIDATE, [key as c_ulong, 0, 0, 0])?;
+        Ok(())
+    }
+
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        check_no_attributes(&options.attributes)?;
+        // Reading a keyring returns the serial numbers of its keys.
+        let keys = match read(libc::KEYCTL_READ, self.keyring()?) {
+            Ok(keys) => keys,
+            // The process keyring doesn't exist until a key is added to it.
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut identifiers = Vec::new();
+        for key in keys.expose().chunks_exact(4) {
+            let key = i32::from_ne_bytes(key.try_into().unwrap());
+            // Keys may have been removed since, or may belong to others.
+            let description = match read(libc::KEYCTL_DESCRIBE, key as c_long) {
+                Ok(description) => description,
+                Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::AccessDenied) => {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            // The description is `type;uid;gid;perm;description`.
+            let Some(description) = std::str::from_utf8(description.expose())
+                .ok()
+                .and_then(|d| d.trim_end_matches('\0').strip_prefix("user;"))
+                .and_then(|d| d.splitn(4, ';').nth(3))
+            else {
+                continue;
+            };
+            let Some(identifier) = self.parse(description) else {
+                continue;
+            };
+            if options.matches(&identifier.as_ref()) {
+                identifiers.push(identifier);
+            }
+        }
+        Ok(identifiers)
+    }
+}
+
+/// Builder to configure a [`KernelKeyring`].
+#[derive(Clone, Debug, Default)]
+pub struct KernelKeyringBuilder {
+    scope: KeyringScope,
+    namespace: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl KernelKeyringBuilder {
+    /// Sets the keyring in which items are stored.
+    ///
+    /// Defaults to [`KeyringScope::User`].
+    pub fn scope(mut self, scope: KeyringScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Sets the namespace, which prefixes the description of every key.
+    ///
+    /// Defaults to `robius-keychain`. The namespace must not be empty or
+    /// contain a `:`.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets how long items are kept after they were stored or their secret
+    /// was last updated, rounded up to whole seconds.
+    ///
+    /// Expired items are removed by the kernel. By default, items don't
+    /// expire, but are still lost when their keyring is.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Creates the keychain.
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] if the namespace is invalid.
+    pub fn build(self) -> Result<KernelKeyring> {
+        let namespace = self
+            .namespace
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned());
+        if namespace.is_empty() || namespace.contains([':', '\0']) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid keyring namespace '{namespace}'"),
+            ));
+        }
+        Ok(KernelKeyring {
+            scope: self.scope,
+            namespace,
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// Returns the key with the given description in the keyring or the
+/// keyrings linked to it.
+fn find(keyring: c_long, description: &CString) -> Result<Option<c_long>> {
+    match keyctl(
+        libc::KEYCTL_SEARCH,
+        [
+            keyring as c_ulong,
+            KEY_TYPE.as_ptr() as c_ulong,
+            description.as_ptr() as c_ulong,
+            0,
+        ],
+    ) {
+        Ok(key) => Ok(Some(key)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the payload or description of a key, growing the buffer until it
+/// fits.
+fn read(operation: u32, key: c_long) -> Result<Secret<Vec<u8>>> {
+    let mut buffer = Secret::new(Vec::new());
+    loop {
+        let capacity = buffer.expose().len();
+        let length = keyctl(
+            operation,
+            [
+                key as c_ulong,
+                buffer.expose_mut().as_mut_ptr() as c_ulong,
+                capacity as c_ulong,
+                0,
+            ],
+        )? as usize;
+        if length <= capacity {
+            buffer.expose_mut().truncate(length);
+            return Ok(buffer);
+        }
+        buffer = Secret::new(vec![0; length]);
+    }
+}
+
+fn keyctl(operation: u32, arguments: [c_ulong; 4]) -> Result<c_long> {
+    let [arg2, arg3, arg4, arg5] = arguments;
+    // SAFETY: Callers pass pointers that are valid for the operation.
+    check(unsafe { libc::syscall(libc::SYS_keyctl, operation, arg2, arg3, arg4, arg5) })
+}
+
+fn check(result: c_long) -> Result<c_long> {
+    if result >= 0 {
+        return Ok(result);
+    }
+    let error = io::Error::last_os_error();
+    let kind = match error.raw_os_error() {
+        Some(libc::ENOKEY | libc::EKEYEXPIRED | libc::EKEYREVOKED) => ErrorKind::NotFound,
+        Some(libc::EACCES | libc::EPERM) => ErrorKind::AccessDenied,
+        // The kernel was built without keys, or a seccomp filter such as the
+        // default one of Docker blocks the system calls.
+        Some(libc::ENOSYS | libc::EOPNOTSUPP) => ErrorKind::Unsupported,
+        // Payloads of `user` keys are limited to 32767 bytes.
+        Some(libc::EINVAL) => ErrorKind::InvalidData,
+        _ => ErrorKind::Other,
+    };
+    Err(Error::new(kind, error))
+}
+
+fn class_name(class: Class) -> &'static str {
+    match class {
+        Class::Generic => "generic",
+        Class::Internet => "internet",
+    }
+}
+
+fn escape(field: &str) -> String {
+    field.replace('%', "%25").replace(':', "%3A")
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('%') {
+        unescaped.push_str(&rest[..i]);
+        match rest.get(i..i + 3)? {
+            "%25" => unescaped.push('%'),
+            "%3A" => unescaped.push(':'),
+            _ => return None,
+        }
+        rest = &rest[i + 3..];
+    }
+    unescaped.push_str(rest);
+    Some(unescaped)
+}
+
+// The kernel has no place for arbitrary key-value pairs.
+fn check_no_attributes(attributes: &[(&str, &str)]) -> Result<()> {
+    if attributes.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "custom attributes are not supported by the kernel keyring",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(namespace: &str) -> KernelKeyring {
+        KernelKeyring::builder()
+            .scope(KeyringScope::Process)
+            .namespace(format!("{namespace}-{}", std::process::id()))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn escaping() {
+        for field in ["", "plain", "a:b", "%3A", "100%", "%:%25::"] {
+            let escaped = escape(field);
+            assert!(!escaped.contains(':'));
+            assert_eq!(unescape(&escaped).as_deref(), Some(field));
+        }
+        assert_eq!(escape("a:b%c"), "a%3Ab%25c");
+        assert_eq!(unescape("%"), None);
+        assert_eq!(unescape("%3a"), None);
+        assert_eq!(unescape("%41"), None);
+    }
+
+    #[test]
+    fn description() {
+        let keyring = KernelKeyring::new(KeyringScope::User);
+        let identifier = Identifier {
+            service: "mail:imap",
+            username: Some("50%"),
+            class: Class::Internet,
+        };
+        let description = keyring.description(&identifier).unwrap();
+        assert_eq!(
+            description.to_str().unwrap(),
+            "robius-keychain:internet:mail%3Aimap:50%25"
+        );
+        assert_eq!(
+            keyring.parse(description.to_str().unwrap()),
+            Some(identifier.to_owned())
+        );
+        assert_eq!(
+            keyring.parse("robius-keychain:generic:mail"),
+            Some(OwnedIdentifier {
+                service: "mail".to_owned(),
+                username: None,
+                class: Class::Generic,
+            })
+        );
+
+        for description in [
+            "other:generic:mail",
+            "robius-keychain-other:generic:mail",
+            "robius-keychain:secret:mail",
+            "robius-keychain:generic",
+            "robius-keychain:generic:mail:user:extra",
+            "robius-keychain:generic:mail%zz",
+        ] {
+            assert_eq!(keyring.parse(description), None, "{description}");
+        }
+
+        let identifier = Identifier {
+            service: "nul\0",
+            username: None,
+            class: Class::Generic,
+        };
+        let error = keyring.description(&identifier).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        for namespace in ["", "a:b", "a\0"] {
+            let error = KernelKeyring::builder()
+                .namespace(namespace)
+                .build()
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn search_before_store() {
+        // The process keyring is only created once a key is added, so the
+        // search runs in a new process.
+        const CHILD: &str = "ROBIUS_KEYCHAIN_TEST_CHILD";
+        if std::env::var_os(CHILD).is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "sys::keyutils::tests::search_before_store"])
+                .env(CHILD, "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        match keyring("search").search(&SearchOptions::new()) {
+            Ok(found) => assert!(found.is_empty()),
+            Err(error) if error.kind() == ErrorKind::Unsupported => {}
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn process_keyring() {
+        let keyring = keyring("contract");
+        match keyring.search(&SearchOptions::new()) {
+            Ok(_) => crate::backend::check_backend(&keyring),
+            // Kernel keyrings are disabled, e.g. by the seccomp filter of a
+            // container.
+            Err(error) if error.kind() == ErrorKind::Unsupported => {}
+            Err(error) => panic!("{error}"),
+        }
+    }
+}
@@ -1,85 +1,306 @@
 use std::collections::HashMap;
 
-use libsecret::{Schema, SchemaAttributeType, SchemaFlags};
+use glib::translate::{from_glib_full, ToGlibPtr};
+use libsecret::{prelude::*, Schema, SchemaAttributeType, SchemaFlags, SearchFlags, ServiceFlags};
 
-use crate::{Error, Identifier, KeychainItemBuilder, Result, UpdateOptions};
+use super::schema::{
+    attributes, check_custom_attributes, class_of, content_type, identifier_of, is_item_of,
+    is_reserved, item_attributes, item_label, renames, timestamp, update_attributes,
+    updated_identifier, updated_label, updates_attributes, CLASS, COMMENT, INTERNET, SCHEMA,
+};
+use crate::{
+    CancellationToken, Class, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder,
+    OwnedIdentifier, Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
 
-pub fn store(item: KeychainItemBuilder) -> Result<()> {
-    let attributes = attributes(item.service, item.username);
+pub fn store(item: &KeychainItemBuilder) -> Result<()> {
+    check_custom_attributes(&item.attributes)?;
 
-    libsecret::password_store_sync(
-        Some(&schema()),
+    let identifier = Identifier {
+        service: item.service,
+        username: item.username,
+        class: item.class,
+    };
+    let existing = find_item(&identifier)?;
+    match (item.mode, &existing) {
+        (StoreMode::CreateNew, Some(_)) => return Err(ErrorKind::Duplicate.into()),
+        (StoreMode::Replace, None) => return Err(ErrorKind::NotFound.into()),
+        _ => {}
+    }
+
+    let attributes = item_attributes(item);
+    let item_schema = schema(attributes.keys().copied());
+    // The existing item is given the new attributes, so that it is replaced
+    // along with its secret and label below. They are restored if that fails,
+    // e.g. because an unlock prompt is dismissed.
+    let mut restore = None;
+    if let Some(existing) = existing {
+        let mut previous = RetrievableExt::attributes(&existing);
+        // libsecret sets the `xdg:` attributes from the schema.
+        previous.retain(|key, _| !key.starts_with("xdg:"));
+        existing
+            .set_attributes_sync(
+                Some(&item_schema),
+                attributes.clone(),
+                cancellable().as_ref(),
+            )
+            .map_err(Error::from)?;
+        restore = Some((existing, previous));
+    }
+
+    let result = libsecret::password_store_binary_sync(
+        Some(&item_schema),
         attributes,
         None,
-        &format!(
-            "Secret for '{}' on '{}'",
-            item.username.unwrap_or(""),
-            item.service
-        ),
-        item.secret,
-        Option::<&gio::Cancellable>::None,
+        &item_label(item),
+        &value(item.secret),
+        cancellable().as_ref(),
     )
-    .map_err(Error)
+    .map_err(Error::from);
+    if let (Err(_), Some((existing, previous))) = (&result, restore) {
+        let previous: HashMap<_, _> = previous
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        // Restoring isn't cancellable, as the store may have failed because it
+        // was cancelled. Its error is returned instead of any from restoring.
+        let _ = existing.set_attributes_sync(
+            Some(&schema(previous.keys().copied())),
+            previous,
+            gio::Cancellable::NONE,
+        );
+    }
+    result
 }
 
-pub fn load(identifier: &Identifier) -> Result<Option<String>> {
-    let attributes = attributes(identifier.service, identifier.username);
-    Ok(libsecret::password_lookup_sync(
-        Some(&schema()),
-        attributes,
-        Option::<&gio::Cancellable>::None,
-    )
-    .map_err(Error)?
-    .map(From::from))
+pub fn load(identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+    let Some(item) = find_item(identifier)? else {
+        return Ok(None);
+    };
+    item.load_secret_sync(cancellable().as_ref())
+        .map_err(Error::from)?;
+    Ok(item.secret().map(|value| Secret::new(value.get())))
 }
 
-pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
-    let secret = if let Some(secret) = options.secret {
-        // TODO: Unnecessary allocation
-        secret.to_owned()
-    } else {
-        // TODO: unwrap
-        load(identifier)?.unwrap()
+pub fn metadata(identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+    let Some(item) = find(identifier)? else {
+        return Ok(None);
     };
 
-    let mut builder =
-        KeychainItemBuilder::new(options.service.unwrap_or(identifier.service), &secret);
+    let mut attributes = item.attributes();
+    let mut metadata = ItemMetadata::new();
+    metadata.class = class_of(&attributes);
+    metadata.created = timestamp(item.created());
+    metadata.modified = timestamp(item.modified());
+    metadata.label = Some(item.label().to_string());
+    metadata.comment = attributes.remove(COMMENT);
+    attributes.retain(|key, _| !is_reserved(key));
+    metadata.attributes = attributes.into_iter().collect();
+    Ok(Some(metadata))
+}
 
-    if let Some(username) = options.username {
-        builder = builder.username(username);
-    } else if let Some(username) = identifier.username {
-        builder = builder.username(username);
+/// Updates the item in place, so it is never missing or duplicated.
+///
+/// The secret is set last, so that it is never stored under the old
+/// attributes. If setting it fails, the item keeps its new attributes and
+/// label along with its old secret.
+pub fn update(identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+    check_custom_attributes(&options.attributes)?;
+    let item = find_item(identifier)?.ok_or(Error::from(ErrorKind::NotFound))?;
+
+    if renames(identifier, options) && find(&updated_identifier(identifier, options))?.is_some() {
+        return Err(ErrorKind::Duplicate.into());
+    }
+
+    if updates_attributes(options) {
+        let mut attributes = RetrievableExt::attributes(&item);
+        // libsecret sets the `xdg:` attributes from the schema.
+        attributes.retain(|key, _| !key.starts_with("xdg:"));
+        update_attributes(&mut attributes, options);
+
+        let attributes: HashMap<_, _> = attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        item.set_attributes_sync(
+            Some(&schema(attributes.keys().copied())),
+            attributes,
+            cancellable().as_ref(),
+        )
+        .map_err(Error::from)?;
+    }
+
+    let label = updated_label(identifier, options, || {
+        Ok(RetrievableExt::label(&item).to_string())
+    })?;
+    if let Some(label) = label {
+        item.set_label_sync(&label, cancellable().as_ref())
+            .map_err(Error::from)?;
+    }
+
+    if let Some(secret) = options.secret {
+        item.set_secret_sync(&value(secret), cancellable().as_ref())
+            .map_err(Error::from)?;
     }
 
-    store(builder)
+    Ok(())
 }
 
+/// Deletes the item of `identifier` only, as clearing by attributes would
+/// also delete items with more attributes.
 pub fn delete(identifier: &Identifier) -> Result<()> {
-    libsecret::password_clear_sync(
-        Some(&schema()),
-        attributes(identifier.service, identifier.username),
-        Option::<&gio::Cancellable>::None,
+    match find_item(identifier)? {
+        Some(item) => item
+            .delete_sync(cancellable().as_ref())
+            .map_err(Error::from),
+        None => Err(ErrorKind::NotFound.into()),
+    }
+}
+
+pub fn search(options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    check_custom_attributes(&options.attributes)?;
+    let mut attributes: HashMap<_, _> = options.attributes.iter().copied().collect();
+    if let Some(service) = options.service {
+        attributes.insert("service", service);
+    }
+    if let Some(username) = options.username {
+        attributes.insert("username", username);
+    }
+    // Generic items have no class attribute, so they are filtered afterwards.
+    if let Some(Class::Internet) = options.class {
+        attributes.insert(CLASS, INTERNET);
+    }
+
+    let items = libsecret::password_search_sync(
+        Some(&schema(options.attributes.iter().map(|&(key, _)| key))),
+        attributes,
+        SearchFlags::ALL,
+        cancellable().as_ref(),
+    )
+    .map_err(Error::from)?;
+
+    Ok(items
+        .into_iter()
+        .filter_map(|item| identifier_of(item.attributes()))
+        .collect())
+}
+
+/// Returns an existing item, unlocking it so that its secret can be accessed
+/// or it can be modified.
+fn find_item(identifier: &Identifier) -> Result<Option<libsecret::Item>> {
+    let service = libsecret::Service::get_sync(ServiceFlags::OPEN_SESSION, cancellable().as_ref())
+        .map_err(Error::from)?;
+    let items = service
+        .search_sync(
+            Some(&schema([])),
+            attributes(identifier.service, identifier.username, identifier.class),
+            SearchFlags::ALL | SearchFlags::UNLOCK,
+            cancellable().as_ref(),
+        )
+        .map_err(Error::from)?;
+    Ok(items
+        .into_iter()
+        .find(|item| is_item_of(&RetrievableExt::attributes(item), identifier)))
+}
+
+/// Returns an existing item without loading its secret.
+fn find(identifier: &Identifier) -> Result<Option<libsecret::Retrievable>> {
+    let items = libsecret::password_search_sync(
+        Some(&schema([])),
+        attributes(identifier.service, identifier.username, identifier.class),
+        SearchFlags::ALL,
+        cancellable().as_ref(),
     )
-    .map_err(Error)
+    .map_err(Error::from)?;
+    // Searches also return items with more attributes.
+    Ok(items
+        .into_iter()
+        .find(|item| is_item_of(&item.attributes(), identifier)))
+}
+
+/// Returns a `gio::Cancellable` that is cancelled along with the current
+/// operation, if it is running as a [`Task`](crate::Task).
+fn cancellable() -> Option<gio::Cancellable> {
+    let token = CancellationToken::current()?;
+    let cancellable = gio::Cancellable::new();
+    let handle = cancellable.clone();
+    token.on_cancel(move || handle.cancel());
+    Some(cancellable)
 }
 
-fn schema() -> Schema {
-    const ATTRIBUTES: [(&str, SchemaAttributeType); 2] = [
+/// Returns the schema for our items.
+///
+/// libsecret rejects attributes that aren't part of the schema, so the keys of
+/// any custom attributes in use must be included.
+fn schema<'a>(keys: impl IntoIterator<Item = &'a str>) -> Schema {
+    const ATTRIBUTES: [(&str, SchemaAttributeType); 3] = [
         ("service", SchemaAttributeType::String),
         ("username", SchemaAttributeType::String),
+        (CLASS, SchemaAttributeType::String),
     ];
-    let attributes = HashMap::from(ATTRIBUTES);
-    Schema::new(
-        "org.freedesktop.Secret.Generic",
-        SchemaFlags::NONE,
-        attributes,
-    )
+    let mut attributes = HashMap::from(ATTRIBUTES);
+    attributes.extend(
+        keys.into_iter()
+            .map(|key| (key, SchemaAttributeType::String)),
+    );
+    Schema::new(SCHEMA, SchemaFlags::NONE, attributes)
+}
+
+fn value(secret: &[u8]) -> libsecret::Value {
+    // `libsecret::Value::new` only accepts UTF-8 secrets.
+    //
+    // SAFETY: `secret_value_new` copies `length` bytes from `secret` and the
+    // NUL-terminated content type, and returns a new reference.
+    unsafe {
+        from_glib_full(libsecret_sys::secret_value_new(
+            secret.as_ptr() as *const _,
+            secret.len() as isize,
+            content_type(secret).to_glib_none().0,
+        ))
+    }
 }
 
-fn attributes<'a>(service: &'a str, username: Option<&'a str>) -> HashMap<&'a str, &'a str> {
-    if let Some(username) = username {
-        HashMap::from([("service", service), ("username", username)])
-    } else {
-        HashMap::from([("service", service)])
+impl From<glib::Error> for Error {
+    fn from(error: glib::Error) -> Self {
+        let kind = if let Some(e) = error.kind::<libsecret::Error>() {
+            match e {
+                libsecret::Error::IsLocked => ErrorKind::Locked,
+                libsecret::Error::NoSuchObject => ErrorKind::NotFound,
+                libsecret::Error::AlreadyExists => ErrorKind::Duplicate,
+                libsecret::Error::InvalidFileFormat => ErrorKind::InvalidData,
+                _ => ErrorKind::Other,
+            }
+        } else if let Some(e) = error.kind::<gio::IOErrorEnum>() {
+            match e {
+                gio::IOErrorEnum::NotFound => ErrorKind::NotFound,
+                gio::IOErrorEnum::Exists => ErrorKind::Duplicate,
+                gio::IOErrorEnum::PermissionDenied => ErrorKind::AccessDenied,
+                gio::IOErrorEnum::Cancelled => ErrorKind::Cancelled,
+                gio::IOErrorEnum::TimedOut => ErrorKind::TimedOut,
+                gio::IOErrorEnum::InvalidData => ErrorKind::InvalidData,
+                gio::IOErrorEnum::NotSupported => ErrorKind::Unsupported,
+                gio::IOErrorEnum::DbusError => ErrorKind::Unavailable,
+                _ => ErrorKind::Other,
+            }
+        } else if let Some(e) = error.kind::<gio::DBusError>() {
+            match e {
+                gio::DBusError::AccessDenied | gio::DBusError::AuthFailed => {
+                    ErrorKind::AccessDenied
+                }
+                gio::DBusError::NotSupported => ErrorKind::Unsupported,
+                gio::DBusError::Timeout | gio::DBusError::TimedOut => ErrorKind::TimedOut,
+                gio::DBusError::ServiceUnknown
+                | gio::DBusError::NameHasNoOwner
+                | gio::DBusError::NoServer
+                | gio::DBusError::NoReply
+                | gio::DBusError::Disconnected
+                | gio::DBusError::SpawnExecFailed
+                | gio::DBusError::SpawnServiceNotFound => ErrorKind::Unavailable,
+                _ => ErrorKind::Other,
+            }
+        } else {
+            ErrorKind::Other
+        };
+        Error::new(kind, error)
     }
 }
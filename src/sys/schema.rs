@@ -0,0 +1,209 @@
+//! The item schema shared by the Secret Service backends, so that items
+//! stored through libsecret and through D-Bus are interchangeable.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    Class, Error, ErrorKind, Identifier, KeychainItemBuilder, OwnedIdentifier, Result,
+    UpdateOptions,
+};
+
+/// The name of the schema, stored in the `xdg:schema` attribute.
+pub const SCHEMA: &str = "org.freedesktop.Secret.Generic";
+
+/// The attribute holding the comment of an item, as the Secret Service has no
+/// dedicated property for it.
+pub const COMMENT: &str = "comment";
+
+/// The attribute holding the class of an item.
+///
+/// It is only set for [`Class::Internet`], so that items without it,
+/// including those stored by other applications, are generic.
+pub const CLASS: &str = "class";
+
+/// The value of [`CLASS`] for [`Class::Internet`].
+pub const INTERNET: &str = "internet";
+
+/// Converts a Secret Service timestamp, which is zero if unknown.
+pub fn timestamp(seconds: u64) -> Option<SystemTime> {
+    match seconds {
+        0 => None,
+        seconds => UNIX_EPOCH.checked_add(Duration::from_secs(seconds)),
+    }
+}
+
+pub fn default_label(service: &str, username: Option<&str>) -> String {
+    format!("Secret for '{}' on '{}'", username.unwrap_or(""), service)
+}
+
+/// Returns the label of a new item.
+pub fn item_label(item: &KeychainItemBuilder) -> String {
+    match item.label {
+        Some(label) => label.to_owned(),
+        None => default_label(item.service, item.username),
+    }
+}
+
+/// Returns the attributes of a new item, including its comment.
+///
+/// The Secret Service only replaces items with exactly the same attributes,
+/// so an existing item is given these attributes before it is replaced.
+/// Otherwise an item with different custom attributes would be left behind.
+pub fn item_attributes<'a>(item: &KeychainItemBuilder<'a>) -> HashMap<&'a str, &'a str> {
+    let mut attributes = attributes(item.service, item.username, item.class);
+    if let Some(comment) = item.comment {
+        attributes.insert(COMMENT, comment);
+    }
+    attributes.extend(item.attributes.iter().copied());
+    attributes
+}
+
+/// Returns the identifier of an item after it is updated with `options`.
+pub fn updated_identifier<'a>(
+    identifier: &Identifier<'a>,
+    options: &UpdateOptions<'a>,
+) -> Identifier<'a> {
+    Identifier {
+        service: options.service.unwrap_or(identifier.service),
+        username: options.username.or(identifier.username),
+        class: options.class.unwrap_or(identifier.class),
+    }
+}
+
+/// Returns whether an update with `options` changes the identifier of an
+/// item.
+pub fn renames(identifier: &Identifier, options: &UpdateOptions) -> bool {
+    updated_identifier(identifier, options) != *identifier
+}
+
+/// Returns whether an update with `options` changes the attributes of an
+/// item.
+pub fn updates_attributes(options: &UpdateOptions) -> bool {
+    options.service.is_some()
+        || options.username.is_some()
+        || options.class.is_some()
+        || options.comment.is_some()
+        || !options.attributes.is_empty()
+}
+
+/// Applies an update with `options` to the attributes of an item.
+pub fn update_attributes(attributes: &mut HashMap<String, String>, options: &UpdateOptions) {
+    if let Some(service) = options.service {
+        attributes.insert("service".to_owned(), service.to_owned());
+    }
+    if let Some(username) = options.username {
+        attributes.insert("username".to_owned(), username.to_owned());
+    }
+    match options.class {
+        Some(Class::Internet) => {
+            attributes.insert(CLASS.to_owned(), INTERNET.to_owned());
+        }
+        Some(Class::Generic) => {
+            attributes.remove(CLASS);
+        }
+        None => {}
+    }
+    if let Some(comment) = options.comment {
+        attributes.insert(COMMENT.to_owned(), comment.to_owned());
+    }
+    for &(key, value) in &options.attributes {
+        attributes.insert(key.to_owned(), value.to_owned());
+    }
+}
+
+/// Returns the label of an item after it is updated with `options`, or `None`
+/// if it doesn't change.
+///
+/// The default label includes the service and username, so it is kept up to
+/// date unless a label was set explicitly. The current label is only read
+/// with `label` if the service or username changes.
+pub fn updated_label(
+    identifier: &Identifier,
+    options: &UpdateOptions,
+    label: impl FnOnce() -> Result<String>,
+) -> Result<Option<String>> {
+    if let Some(label) = options.label {
+        return Ok(Some(label.to_owned()));
+    }
+    let target = updated_identifier(identifier, options);
+    let renamed = target.service != identifier.service || target.username != identifier.username;
+    if renamed && label()? == default_label(identifier.service, identifier.username) {
+        Ok(Some(default_label(target.service, target.username)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the identifier of an item found by a search, or `None` if the item
+/// has no service.
+pub fn identifier_of(mut attributes: HashMap<String, String>) -> Option<OwnedIdentifier> {
+    let class = class_of(&attributes);
+    Some(OwnedIdentifier {
+        service: attributes.remove("service")?,
+        username: attributes.remove("username"),
+        class,
+    })
+}
+
+/// Returns the class of an item with `attributes`.
+pub fn class_of(attributes: &HashMap<String, String>) -> Class {
+    match attributes.get(CLASS).map(String::as_str) {
+        Some(INTERNET) => Class::Internet,
+        _ => Class::Generic,
+    }
+}
+
+pub fn check_custom_attributes(attributes: &[(&str, &str)]) -> Result<()> {
+    match attributes.iter().find(|(key, _)| is_reserved(key)) {
+        Some((key, _)) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("attribute key '{key}' is reserved"),
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn is_reserved(key: &str) -> bool {
+    key == "service"
+        || key == "username"
+        || key == CLASS
+        || key == COMMENT
+        || key.starts_with("xdg:")
+}
+
+pub fn content_type(secret: &[u8]) -> &'static str {
+    // Keyring managers only display secrets with a text content type.
+    match std::str::from_utf8(secret) {
+        Ok(_) => "text/plain",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+pub fn attributes<'a>(
+    service: &'a str,
+    username: Option<&'a str>,
+    class: Class,
+) -> HashMap<&'a str, &'a str> {
+    let mut attributes = HashMap::from([("service", service)]);
+    if let Some(username) = username {
+        attributes.insert("username", username);
+    }
+    if class == Class::Internet {
+        attributes.insert(CLASS, INTERNET);
+    }
+    attributes
+}
+
+/// Returns whether an item with `attributes` is the item of `identifier`.
+///
+/// Searches also return items with more attributes than those searched for,
+/// so without a username they would match the items of every username, and
+/// generic ones would match internet items.
+pub fn is_item_of(attributes: &HashMap<String, String>, identifier: &Identifier) -> bool {
+    attributes.get("service").map(String::as_str) == Some(identifier.service)
+        && attributes.get("username").map(String::as_str) == identifier.username
+        && class_of(attributes) == identifier.class
+}
@@ -0,0 +1,636 @@
+mod session;
+
+use std::{collections::HashMap, fmt};
+
+use zbus::{
+    blocking::{connection, proxy::Builder as ProxyBuilder, Connection, Proxy},
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    CacheProperties, DBusError,
+};
+
+use self::session::Session;
+use super::schema::{
+    attributes, check_custom_attributes, class_of, content_type, identifier_of, is_item_of,
+    is_reserved, item_attributes, item_label, renames, timestamp, update_attributes,
+    updated_identifier, updated_label, updates_attributes, CLASS, COMMENT, INTERNET, SCHEMA,
+};
+use crate::{
+    Backend, CancellationToken, Class, Error, ErrorKind, Identifier, ItemMetadata,
+    KeychainItemBuilder, OwnedIdentifier, Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
+
+const DESTINATION: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+
+const SERVICE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION: &str = "org.freedesktop.Secret.Collection";
+const ITEM: &str = "org.freedesktop.Secret.Item";
+const PROMPT: &str = "org.freedesktop.Secret.Prompt";
+
+/// A secret as transferred over D-Bus: the session, the algorithm
+/// parameters, the value and its content type.
+type SecretStruct = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+/// A keychain backend talking to the Secret Service directly over D-Bus,
+/// without libsecret.
+///
+/// Items use the same schema as the libsecret backend, so items stored by
+/// either are visible to both, and to other applications using libsecret.
+/// New items are stored in the default collection, which is created if it
+/// doesn't exist yet.
+///
+/// Secrets are encrypted on the bus if the service supports it, see
+/// [`SecretServiceBuilder::encryption`].
+///
+/// With the `libsecret` feature disabled, this is the [`Platform`] backend on
+/// Linux.
+///
+/// ```no_run
+/// use robius_keychain::{Keychain, SecretService};
+///
+/// let service = SecretService::connect().expect("no Secret Service running");
+/// Keychain::set_default(Keychain::new(service));
+/// ```
+///
+/// [`Platform`]: crate::Platform
+pub struct SecretService {
+    connection: Connection,
+    session: Session,
+}
+
+impl SecretService {
+    /// Connects to the Secret Service on the session bus.
+    pub fn connect() -> Result<Self> {
+        Self::builder().connect()
+    }
+
+    /// Returns a builder to configure the connection.
+    pub fn builder() -> SecretServiceBuilder {
+        SecretServiceBuilder::default()
+    }
+
+    /// Returns whether secrets are encrypted on the bus.
+    pub fn is_encrypted(&self) -> bool {
+        self.session.is_encrypted()
+    }
+
+    fn proxy<'p>(&self, path: ObjectPath<'p>, interface: &'static str) -> Result<Proxy<'p>> {
+        proxy(&self.connection, path, interface)
+    }
+
+    fn service(&self) -> Result<Proxy<'static>> {
+        self.proxy(ObjectPath::from_static_str_unchecked(SERVICE_PATH), SERVICE)
+    }
+
+    /// Returns the unlocked and the locked items with the given attributes.
+    fn search_items(
+        &self,
+        attributes: &HashMap<&str, &str>,
+    ) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)> {
+        Ok(self.service()?.call("SearchItems", &(attributes,))?)
+    }
+
+    /// Returns the unlocked and the locked items of an identifier.
+    fn items(
+        &self,
+        identifier: &Identifier,
+    ) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>)> {
+        let mut attributes = attributes(identifier.service, identifier.username, identifier.class);
+        attributes.insert("xdg:schema", SCHEMA);
+        let (unlocked, locked) = self.search_items(&attributes)?;
+        Ok((
+            self.items_of(unlocked, identifier)?,
+            self.items_of(locked, identifier)?,
+        ))
+    }
+
+    /// Filters search results to the items of an identifier.
+    fn items_of(
+        &self,
+        items: Vec<OwnedObjectPath>,
+        identifier: &Identifier,
+    ) -> Result<Vec<OwnedObjectPath>> {
+        let mut matching = Vec::new();
+        for item in items {
+            let attributes: HashMap<String, String> = self
+                .proxy(item.as_ref(), ITEM)?
+                .get_property("Attributes")?;
+            if is_item_of(&attributes, identifier) {
+                matching.push(item);
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Returns an existing item without unlocking it.
+    fn find(&self, identifier: &Identifier) -> Result<Option<OwnedObjectPath>> {
+        let (unlocked, locked) = self.items(identifier)?;
+        Ok(unlocked.into_iter().chain(locked).next())
+    }
+
+    /// Returns an existing item, unlocking it so that its secret can be
+    /// accessed.
+    fn find_unlocked(&self, identifier: &Identifier) -> Result<Option<OwnedObjectPath>> {
+        let (unlocked, locked) = self.items(identifier)?;
+        if let Some(item) = unlocked.into_iter().next() {
+            return Ok(Some(item));
+        }
+        let Some(item) = locked.into_iter().next() else {
+            return Ok(None);
+        };
+        self.unlock(std::slice::from_ref(&item))?;
+        Ok(Some(item))
+    }
+
+    /// Returns the default collection, creating it if there is none.
+    fn default_collection(&self) -> Result<OwnedObjectPath> {
+        let service = self.service()?;
+        let collection: OwnedObjectPath = service.call("ReadAlias", &("default",))?;
+        if collection.as_str() != "/" {
+            return Ok(collection);
+        }
+
+        let properties = HashMap::from([(
+            "org.freedesktop.Secret.Collection.Label",
+            Value::from("Default keyring"),
+        )]);
+        let (collection, prompt): (OwnedObjectPath, OwnedObjectPath) =
+            service.call("CreateCollection", &(properties, "default"))?;
+        if collection.as_str() != "/" {
+            return Ok(collection);
+        }
+        match self.prompt(prompt)? {
+            Some(result) => Ok(OwnedObjectPath::try_from(result).map_err(zbus::Error::from)?),
+            None => Err(Error::new(
+                ErrorKind::Other,
+                "failed to create the default collection",
+            )),
+        }
+    }
+
+    /// Unlocks items or collections, prompting the user if needed.
+    fn unlock(&self, objects: &[OwnedObjectPath]) -> Result<()> {
+        let (_, prompt): (Vec<OwnedObjectPath>, OwnedObjectPath) =
+            self.service()?.call("Unlock", &(objects,))?;
+        self.prompt(prompt)?;
+        Ok(())
+    }
+
+    /// Shows a prompt, unless the path is `/`, and waits for the user to
+    /// complete it.
+    ///
+    /// The prompt is dismissed if the operation is cancelled.
+    fn prompt(&self, prompt: OwnedObjectPath) -> Result<Option<OwnedValue>> {
+        if prompt.as_str() == "/" {
+            return Ok(None);
+        }
+
+        let proxy = proxy(&self.connection, prompt.into_inner(), PROMPT)?;
+        let mut completed = proxy.receive_signal("Completed")?;
+        // There is no parent window to attach the prompt to.
+        proxy.call_method("Prompt", &("",))?;
+        if let Some(token) = CancellationToken::current() {
+            let proxy = proxy.clone();
+            token.on_cancel(move || {
+                let _ = proxy.call_noreply("Dismiss", &());
+            });
+        }
+
+        let message = completed.next().ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unavailable,
+                "the Secret Service disconnected during a prompt",
+            )
+        })?;
+        let (dismissed, result): (bool, OwnedValue) = message.body().deserialize()?;
+        if dismissed {
+            return Err(ErrorKind::Cancelled.into());
+        }
+        Ok(Some(result))
+    }
+
+    /// Creates an item in the default collection, replacing an item with the
+    /// same attributes.
+    fn create_item(
+        &self,
+        item: &KeychainItemBuilder,
+        attributes: HashMap<&str, &str>,
+    ) -> Result<()> {
+        let properties = HashMap::from([
+            (
+                "org.freedesktop.Secret.Item.Label",
+                Value::from(item_label(item)),
+            ),
+            (
+                "org.freedesktop.Secret.Item.Attributes",
+                Value::from(attributes),
+            ),
+        ]);
+
+        let collection = self.default_collection()?;
+        let proxy = self.proxy(collection.as_ref(), COLLECTION)?;
+        if proxy.get_property::<bool>("Locked")? {
+            self.unlock(std::slice::from_ref(&collection))?;
+        }
+        let (parameters, value) = self.session.encrypt(item.secret)?;
+        let secret = (
+            &self.session.path,
+            parameters,
+            value.expose(),
+            content_type(item.secret),
+        );
+        let (_, prompt): (OwnedObjectPath, OwnedObjectPath) =
+            proxy.call("CreateItem", &(properties, secret, true))?;
+        self.prompt(prompt)?;
+        Ok(())
+    }
+}
+
+impl Backend for SecretService {
+    fn store(&self, item: &KeychainItemBuilder) -> Result<()> {
+        check_custom_attributes(&item.attributes)?;
+
+        let identifier = Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        };
+        let existing = self.find(&identifier)?;
+        match (item.mode, &existing) {
+            (StoreMode::CreateNew, Some(_)) => return Err(ErrorKind::Duplicate.into()),
+            (StoreMode::Replace, None) => return Err(ErrorKind::NotFound.into()),
+            _ => {}
+        }
+
+        let mut attributes = item_attributes(item);
+        attributes.insert("xdg:schema", SCHEMA);
+        // The existing item is given the new attributes, so that `CreateItem`
+        // replaces it along with its secret and label. They are restored if
+        // that fails, e.g. because an unlock prompt is dismissed.
+        let mut restore = None;
+        if let Some(existing) = &existing {
+            let proxy = self.proxy(existing.as_ref(), ITEM)?;
+            if proxy.get_property::<bool>("Locked")? {
+                self.unlock(std::slice::from_ref(existing))?;
+            }
+            let previous: HashMap<String, String> = proxy.get_property("Attributes")?;
+            proxy.set_property("Attributes", attributes.clone())?;
+            restore = Some((proxy, previous));
+        }
+
+        let result = self.create_item(item, attributes);
+        if let (Err(_), Some((proxy, previous))) = (&result, restore) {
+            // The original error is more useful than one from restoring.
+            let _ = proxy.set_property("Attributes", previous);
+        }
+        result
+    }
+
+    fn load(&self, identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        let Some(item) = self.find_unlocked(identifier)? else {
+            return Ok(None);
+        };
+        let (_, parameters, value, _): SecretStruct = self
+            .proxy(item.as_ref(), ITEM)?
+            .call("GetSecret", &(&self.session.path,))?;
+        Ok(Some(self.session.decrypt(&parameters, value)?))
+    }
+
+    fn metadata(&self, identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        let Some(item) = self.find(identifier)? else {
+            return Ok(None);
+        };
+        let proxy = self.proxy(item.as_ref(), ITEM)?;
+
+        let mut attributes: HashMap<String, String> = proxy.get_property("Attributes")?;
+        let mut metadata = ItemMetadata::new();
+        metadata.class = class_of(&attributes);
+        metadata.created = timestamp(proxy.get_property("Created")?);
+        metadata.modified = timestamp(proxy.get_property("Modified")?);
+        metadata.label = Some(proxy.get_property("Label")?);
+        metadata.comment = attributes.remove(COMMENT);
+        attributes.retain(|key, _| !is_reserved(key));
+        metadata.attributes = attributes.into_iter().collect();
+        Ok(Some(metadata))
+    }
+
+    /// Updates the item in place, so it is never missing or duplicated.
+    ///
+    /// The secret is set last, so that it is never stored under the old
+    /// attributes. If setting it fails, the item keeps its new attributes and
+    /// label along with its old secret.
+    fn update(&self, identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        check_custom_attributes(&options.attributes)?;
+        let item = self
+            .find_unlocked(identifier)?
+            .ok_or(Error::from(ErrorKind::NotFound))?;
+        if renames(identifier, options)
+            && self
+                .find(&updated_identifier(identifier, options))?
+                .is_some()
+        {
+            return Err(ErrorKind::Duplicate.into());
+        }
+
+        let proxy = self.proxy(item.as_ref(), ITEM)?;
+
+        if updates_attributes(options) {
+            let mut attributes: HashMap<String, String> = proxy.get_property("Attributes")?;
+            update_attributes(&mut attributes, options);
+            proxy.set_property("Attributes", attributes)?;
+        }
+
+        let label = updated_label(identifier, options, || {
+            Ok(proxy.get_property::<String>("Label")?)
+        })?;
+        if let Some(label) = label {
+            proxy.set_property("Label", label)?;
+        }
+
+        if let Some(secret) = options.secret {
+            let (parameters, value) = self.session.encrypt(secret)?;
+            let secret = (
+                &self.session.path,
+                parameters,
+                value.expose(),
+                content_type(secret),
+            );
+            proxy.call_method("SetSecret", &(secret,))?;
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, identifier: &Identifier) -> Result<()> {
+        let (unlocked, locked) = self.items(identifier)?;
+        if unlocked.is_empty() && locked.is_empty() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        if !locked.is_empty() {
+            self.unlock(&locked)?;
+        }
+        for item in unlocked.iter().chain(&locked) {
+            let prompt: OwnedObjectPath = self.proxy(item.as_ref(), ITEM)?.call("Delete", &())?;
+            self.prompt(prompt)?;
+        }
+        Ok(())
+    }
+
+    fn search(&self, options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        check_custom_attributes(&options.attributes)?;
+        let mut attributes: HashMap<_, _> = options.attributes.iter().copied().collect();
+        attributes.insert("xdg:schema", SCHEMA);
+        if let Some(service) = options.service {
+            attributes.insert("service", service);
+        }
+        if let Some(username) = options.username {
+            attributes.insert("username", username);
+        }
+        // Generic items have no class attribute, so they are filtered
+        // afterwards.
+        if let Some(Class::Internet) = options.class {
+            attributes.insert(CLASS, INTERNET);
+        }
+
+        let (unlocked, locked) = self.search_items(&attributes)?;
+        let mut identifiers = Vec::new();
+        for item in unlocked.iter().chain(&locked) {
+            let attributes = self
+                .proxy(item.as_ref(), ITEM)?
+                .get_property("Attributes")?;
+            identifiers.extend(identifier_of(attributes));
+        }
+        Ok(identifiers)
+    }
+}
+
+impl fmt::Debug for SecretService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecretService")
+            .field("session", &self.session.path.as_str())
+            .field("encrypted", &self.is_encrypted())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builder to configure a [`SecretService`].
+#[derive(Clone, Debug, Default)]
+pub struct SecretServiceBuilder {
+    address: Option<String>,
+    encryption: SessionEncryption,
+}
+
+impl SecretServiceBuilder {
+    /// Sets the address of the bus to connect to, instead of the session bus.
+    ///
+    /// This is mostly useful to test against a stand-in Secret Service on a
+    /// private bus, e.g. `unix:path=/tmp/test-bus`.
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    /// Sets whether secrets are encrypted on the bus.
+    ///
+    /// Defaults to [`SessionEncryption::Preferred`].
+    pub fn encryption(mut self, encryption: SessionEncryption) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Connects to the Secret Service and opens a session.
+    ///
+    /// Fails with [`ErrorKind::Unavailable`] if the bus can't be reached or
+    /// no Secret Service is running on it, and with
+    /// [`ErrorKind::Unsupported`] if encryption is required but not
+    /// supported by the service.
+    pub fn connect(self) -> Result<SecretService> {
+        let connection = match self.address {
+            Some(address) => connection::Builder::address(address.as_str())?.build()?,
+            None => Connection::session()?,
+        };
+        let service = proxy(
+            &connection,
+            ObjectPath::from_static_str_unchecked(SERVICE_PATH),
+            SERVICE,
+        )?;
+        let session = Session::open(&service, self.encryption)?;
+        Ok(SecretService {
+            connection,
+            session,
+        })
+    }
+}
+
+/// How secrets are protected while they are transferred between the process
+/// and the Secret Service.
+///
+/// Without encryption, secrets are sent in cleartext, which other processes
+/// that can monitor the bus are able to read. Encrypted sessions use the
+/// `dh-ietf1024-sha256-aes128-cbc-pkcs7` algorithm, which libsecret also
+/// prefers.
+///
+/// Only this client can require encryption. libsecret, which the platform
+/// keychain uses with the `libsecret` feature, falls back to cleartext if the
+/// service doesn't support encryption.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SessionEncryption {
+    /// Encrypt secrets if the service supports it, and send them in
+    /// cleartext otherwise.
+    #[default]
+    Preferred,
+    /// Encrypt secrets, and fail to connect if the service doesn't support
+    /// it.
+    Required,
+    /// Send secrets in cleartext.
+    Plain,
+}
+
+/// The platform keychain, if the `libsecret` feature is disabled.
+pub(super) mod platform {
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    use super::{dbus_error_name, SecretService};
+    use crate::{
+        Backend, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier,
+        Result, SearchOptions, Secret, UpdateOptions,
+    };
+
+    pub fn store(item: &KeychainItemBuilder) -> Result<()> {
+        with_service(|service| service.store(item))
+    }
+
+    pub fn load(identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+        with_service(|service| service.load(identifier))
+    }
+
+    pub fn metadata(identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+        with_service(|service| service.metadata(identifier))
+    }
+
+    pub fn update(identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+        with_service(|service| service.update(identifier, options))
+    }
+
+    pub fn delete(identifier: &Identifier) -> Result<()> {
+        with_service(|service| service.delete(identifier))
+    }
+
+    pub fn search(options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+        with_service(|service| service.search(options))
+    }
+
+    /// Runs `f` with the Secret Service on the session bus, reconnecting once
+    /// if the connection or session was lost, e.g. because the service was
+    /// restarted.
+    fn with_service<T>(f: impl Fn(&SecretService) -> Result<T>) -> Result<T> {
+        let current = service(None)?;
+        match f(&current) {
+            Err(error) if is_disconnected(&error) => f(&*service(Some(&current))?),
+            result => result,
+        }
+    }
+
+    /// Returns the Secret Service on the session bus, connecting if there is
+    /// no connection yet or the current one is `stale`.
+    fn service(stale: Option<&Arc<SecretService>>) -> Result<Arc<SecretService>> {
+        static SERVICE: Mutex<Option<Arc<SecretService>>> = Mutex::new(None);
+
+        let mut service = SERVICE.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*service {
+            // Another thread may have reconnected already.
+            Some(current) if !stale.is_some_and(|stale| Arc::ptr_eq(stale, current)) => {
+                return Ok(current.clone());
+            }
+            _ => {}
+        }
+        let connected = Arc::new(SecretService::connect()?);
+        *service = Some(connected.clone());
+        Ok(connected)
+    }
+
+    /// Returns whether `error` means that the connection or session is no
+    /// longer usable.
+    ///
+    /// Missing objects aren't included, as items are also missing when they
+    /// are deleted concurrently, and the operation would be repeated.
+    fn is_disconnected(error: &Error) -> bool {
+        error.kind() == ErrorKind::Unavailable
+            || dbus_error_name(error)
+                .is_some_and(|name| name == "org.freedesktop.Secret.Error.NoSession")
+    }
+}
+
+fn proxy<'p>(
+    connection: &Connection,
+    path: ObjectPath<'p>,
+    interface: &'static str,
+) -> Result<Proxy<'p>> {
+    Ok(ProxyBuilder::new(connection)
+        .destination(DESTINATION)?
+        .path(path)?
+        .interface(interface)?
+        .cache_properties(CacheProperties::No)
+        .build()?)
+}
+
+/// Returns the name of the D-Bus error that caused `error`, if any.
+fn dbus_error_name(error: &Error) -> Option<String> {
+    let source = std::error::Error::source(error)?;
+    if let Some(error) = source.downcast_ref::<zbus::fdo::Error>() {
+        return Some(error.name().to_string());
+    }
+    match source.downcast_ref::<zbus::Error>()? {
+        zbus::Error::MethodError(name, _, _) => Some(name.to_string()),
+        zbus::Error::FDO(error) => Some(error.name().to_string()),
+        _ => None,
+    }
+}
+
+/// Maps the name of a D-Bus error to an error kind.
+fn error_kind(name: &str) -> ErrorKind {
+    match name {
+        "org.freedesktop.Secret.Error.IsLocked" => ErrorKind::Locked,
+        "org.freedesktop.Secret.Error.NoSuchObject"
+        | "org.freedesktop.DBus.Error.UnknownObject" => ErrorKind::NotFound,
+        "org.freedesktop.DBus.Error.AccessDenied" | "org.freedesktop.DBus.Error.AuthFailed" => {
+            ErrorKind::AccessDenied
+        }
+        "org.freedesktop.DBus.Error.NotSupported" => ErrorKind::Unsupported,
+        "org.freedesktop.DBus.Error.Timeout" | "org.freedesktop.DBus.Error.TimedOut" => {
+            ErrorKind::TimedOut
+        }
+        "org.freedesktop.DBus.Error.ServiceUnknown"
+        | "org.freedesktop.DBus.Error.NameHasNoOwner"
+        | "org.freedesktop.DBus.Error.NoServer"
+        | "org.freedesktop.DBus.Error.NoReply"
+        | "org.freedesktop.DBus.Error.Disconnected"
+        | "org.freedesktop.DBus.Error.Spawn.ExecFailed"
+        | "org.freedesktop.DBus.Error.Spawn.ServiceNotFound" => ErrorKind::Unavailable,
+        _ => ErrorKind::Other,
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(error: zbus::Error) -> Self {
+        let kind = match &error {
+            zbus::Error::MethodError(name, _, _) => error_kind(name.as_str()),
+            zbus::Error::FDO(e) => error_kind(e.name().as_str()),
+            zbus::Error::Address(_) | zbus::Error::InputOutput(_) | zbus::Error::Handshake(_) => {
+                ErrorKind::Unavailable
+            }
+            _ => ErrorKind::Other,
+        };
+        Error::new(kind, error)
+    }
+}
+
+impl From<zbus::fdo::Error> for Error {
+    fn from(error: zbus::fdo::Error) -> Self {
+        match error {
+            zbus::fdo::Error::ZBus(e) => Error::from(e),
+            error => Error::new(error_kind(error.name().as_str()), error),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use aes::Aes128;
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use sha2::Sha256;
+use zbus::{
+    blocking::Proxy,
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+};
+use zeroize::Zeroize;
+
+use crate::{Error, ErrorKind, Result, Secret, SessionEncryption};
+
+/// Diffie-Hellman key exchange in the Second Oakley Group, HKDF-SHA256 key
+/// derivation and AES-128-CBC with PKCS#7 padding.
+const ENCRYPTED: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+const PLAIN: &str = "plain";
+
+/// The prime of the Second Oakley Group from RFC 2409, whose generator is 2.
+const PRIME: [u8; 128] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x0f, 0xda, 0xa2, 0x21, 0x68, 0xc2, 0x34,
+    0xc4, 0xc6, 0x62, 0x8b, 0x80, 0xdc, 0x1c, 0xd1, 0x29, 0x02, 0x4e, 0x08, 0x8a, 0x67, 0xcc, 0x74,
+    0x02, 0x0b, 0xbe, 0xa6, 0x3b, 0x13, 0x9b, 0x22, 0x51, 0x4a, 0x08, 0x79, 0x8e, 0x34, 0x04, 0xdd,
+    0xef, 0x95, 0x19, 0xb3, 0xcd, 0x3a, 0x43, 0x1b, 0x30, 0x2b, 0x0a, 0x6d, 0xf2, 0x5f, 0x14, 0x37,
+    0x4f, 0xe1, 0x35, 0x6d, 0x6d, 0x51, 0xc2, 0x45, 0xe4, 0x85, 0xb5, 0x76, 0x62, 0x5e, 0x7e, 0xc6,
+    0xf4, 0x4c, 0x42, 0xe9, 0xa6, 0x37, 0xed, 0x6b, 0x0b, 0xff, 0x5c, 0xb6, 0xf4, 0x06, 0xb7, 0xed,
+    0xee, 0x38, 0x6b, 0xfb, 0x5a, 0x89, 0x9f, 0xa5, 0xae, 0x9f, 0x24, 0x11, 0x7c, 0x4b, 0x1f, 0xe6,
+    0x49, 0x28, 0x66, 0x51, 0xec, 0xe6, 0x53, 0x81, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// A session with the Secret Service, which determines how secrets are
+/// transferred.
+pub struct Session {
+    pub path: OwnedObjectPath,
+    /// The AES key if secrets are encrypted.
+    key: Option<Secret<[u8; 16]>>,
+}
+
+impl Session {
+    /// Opens a session, negotiating the algorithm according to `encryption`.
+    pub fn open(service: &Proxy, encryption: SessionEncryption) -> Result<Self> {
+        if encryption == SessionEncryption::Plain {
+            return Self::open_plain(service);
+        }
+        match Self::open_encrypted(service) {
+            Err(e) if e.kind() == ErrorKind::Unsupported => match encryption {
+                SessionEncryption::Required => Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "the Secret Service doesn't support encrypted sessions",
+                )),
+                _ => Self::open_plain(service),
+            },
+            result => result,
+        }
+    }
+
+    fn open_plain(service: &Proxy) -> Result<Self> {
+        let (_, path): (OwnedValue, OwnedObjectPath) =
+            service.call("OpenSession", &(PLAIN, Value::from("")))?;
+        Ok(Self { path, key: None })
+    }
+
+    fn open_encrypted(service: &Proxy) -> Result<Self> {
+        let prime = BigUint::from_bytes_be(&PRIME);
+        let mut bytes = [0; 128];
+        random(&mut bytes)?;
+        let private_key = BigUint::from_bytes_be(&bytes);
+        bytes.zeroize();
+        let public_key = BigUint::from(2u32).modpow(&private_key, &prime);
+
+        let (output, path): (OwnedValue, OwnedObjectPath) = service.call(
+            "OpenSession",
+            &(ENCRYPTED, Value::from(public_key.to_bytes_be())),
+        )?;
+        let service_key = Vec::<u8>::try_from(output).map_err(zbus::Error::from)?;
+        let key = derive_key(&private_key, &service_key)?;
+        Ok(Self {
+            path,
+            key: Some(key),
+        })
+    }
+
+    /// Returns whether secrets are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Returns the parameters and value to send a secret.
+    pub fn encrypt(&self, secret: &[u8]) -> Result<(Vec<u8>, Secret<Vec<u8>>)> {
+        let Some(key) = &self.key else {
+            return Ok((Vec::new(), Secret::new(secret.to_vec())));
+        };
+        let mut iv = [0; 16];
+        random(&mut iv)?;
+        let value = cbc::Encryptor::<Aes128>::new(key.expose().into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(secret);
+        Ok((iv.to_vec(), Secret::new(value)))
+    }
+
+    /// Returns a secret received with the given parameters and value.
+    pub fn decrypt(&self, parameters: &[u8], value: Vec<u8>) -> Result<Secret<Vec<u8>>> {
+        let value = Secret::new(value);
+        let Some(key) = &self.key else {
+            return Ok(value);
+        };
+        let iv = <[u8; 16]>::try_from(parameters).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "the Secret Service sent an invalid initialization vector",
+            )
+        })?;
+        cbc::Decryptor::<Aes128>::new(key.expose().into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(value.expose())
+            .map(Secret::new)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "the Secret Service sent a malformed secret",
+                )
+            })
+    }
+}
+
+/// Derives the AES key from our private key and the public key of the service.
+fn derive_key(private_key: &BigUint, service_key: &[u8]) -> Result<Secret<[u8; 16]>> {
+    let prime = BigUint::from_bytes_be(&PRIME);
+    let service_key = BigUint::from_bytes_be(service_key);
+    // Keys outside of [2, p - 2] would give away the shared secret.
+    if service_key < BigUint::from(2u32) || service_key > &prime - 2u32 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the Secret Service sent an invalid public key",
+        ));
+    }
+
+    // The shared secret is padded to the size of the prime.
+    let mut shared_secret = [0; 128];
+    let bytes = service_key.modpow(private_key, &prime).to_bytes_be();
+    shared_secret[128 - bytes.len()..].copy_from_slice(&bytes);
+
+    let mut key = Secret::new([0; 16]);
+    Hkdf::<Sha256>::new(None, &shared_secret)
+        .expand(&[], key.expose_mut())
+        .expect("16 bytes is a valid length for HKDF-SHA256");
+    shared_secret.zeroize();
+    Ok(key)
+}
+
+fn random(bytes: &mut [u8]) -> Result<()> {
+    getrandom::getrandom(bytes).map_err(|e| Error::new(ErrorKind::Other, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn session(key: [u8; 16]) -> Session {
+        Session {
+            path: OwnedObjectPath::try_from("/").unwrap(),
+            key: Some(Secret::new(key)),
+        }
+    }
+
+    /// The expected values were computed independently with the Python
+    /// `cryptography` package.
+    #[test]
+    fn known_answer() {
+        let prime = BigUint::from_bytes_be(&PRIME);
+        let private_key = BigUint::from_bytes_be(&(1..=32).collect::<Vec<u8>>());
+        let service_private_key = BigUint::from_bytes_be(&(100..132).collect::<Vec<u8>>());
+        let service_key = BigUint::from(2u32).modpow(&service_private_key, &prime);
+
+        let key = derive_key(&private_key, &service_key.to_bytes_be()).unwrap();
+        assert_eq!(&key.expose()[..], hex("895b1d99003ef9224edaac3b44199a1d"));
+
+        let iv: Vec<u8> = (0..16).collect();
+        let session = session(*key.expose());
+        let secret = session
+            .decrypt(&iv, hex("eeffc9db7f24a4d4d3d6e57b96178857"))
+            .unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+    }
+
+    #[test]
+    fn roundtrip() {
+        let session = session([7; 16]);
+        for secret in [&b""[..], b"hunter2", &[0xff; 16], &[0; 100]] {
+            let (iv, value) = session.encrypt(secret).unwrap();
+            assert_eq!(iv.len(), 16);
+            assert_eq!(value.expose().len(), (secret.len() / 16 + 1) * 16);
+            let decrypted = session.decrypt(&iv, value.expose().clone()).unwrap();
+            assert_eq!(decrypted.expose(), secret);
+        }
+
+        let (iv, value) = session.encrypt(b"hunter2").unwrap();
+        let error = session
+            .decrypt(&iv[1..], value.expose().clone())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = session.decrypt(&iv, hex("00")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_public_keys() {
+        let prime = BigUint::from_bytes_be(&PRIME);
+        let private_key = BigUint::from(12345u32);
+        for service_key in [
+            BigUint::from(0u32),
+            BigUint::from(1u32),
+            &prime - 1u32,
+            prime.clone(),
+            &prime + 2u32,
+        ] {
+            let error = derive_key(&private_key, &service_key.to_bytes_be()).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        assert!(derive_key(&private_key, &[2]).is_ok());
+        assert!(derive_key(&private_key, &(&prime - 2u32).to_bytes_be()).is_ok());
+    }
+}
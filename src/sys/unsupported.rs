@@ -1,9 +1,28 @@
-use crate::{Identifier, KeychainItemBuilder, Result};
+use crate::{
+    ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier, Result,
+    SearchOptions, Secret, UpdateOptions,
+};
 
-pub fn store(item: KeychainItemBuilder) -> Result<()> {
-    todo!();
+pub fn store(_: &KeychainItemBuilder) -> Result<()> {
+    Err(ErrorKind::Unsupported.into())
 }
 
-pub fn load(identifier: Identifier) -> Result<Option<String>> {
-    todo!();
+pub fn load(_: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+pub fn metadata(_: &Identifier) -> Result<Option<ItemMetadata>> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+pub fn update(_: &Identifier, _: &UpdateOptions) -> Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+pub fn delete(_: &Identifier) -> Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+pub fn search(_: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    Err(ErrorKind::Unsupported.into())
 }
@@ -1,33 +1,64 @@
-use std::{ptr, slice, str};
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    ptr, slice,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use windows::{
-    core::PWSTR,
+    core::{PCWSTR, PWSTR},
     Win32::{
         Foundation::FILETIME,
         Security::Credentials::{
-            CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_FLAGS, CRED_PERSIST,
-            CRED_TYPE,
+            CredDeleteW, CredEnumerateW, CredFree, CredReadW, CredWriteW, CREDENTIALW,
+            CREDENTIAL_ATTRIBUTEW, CRED_ENUMERATE_FLAGS, CRED_FLAGS, CRED_PERSIST, CRED_TYPE,
         },
     },
 };
+use windows_result::HRESULT;
 
-use crate::{Class, Error, Identifier, KeychainItemBuilder, Result, UpdateOptions};
+use crate::{
+    Class, Error, ErrorKind, Identifier, ItemMetadata, KeychainItemBuilder, OwnedIdentifier,
+    Result, SearchOptions, Secret, StoreMode, UpdateOptions,
+};
 
 const TARGET_NAME_SEPARATOR: char = '\x1f';
 
-pub fn store(item: KeychainItemBuilder) -> Result<()> {
+pub fn store(item: &KeychainItemBuilder) -> Result<()> {
+    // `CredWriteW` always replaces an existing credential.
+    if item.mode != StoreMode::Upsert {
+        let exists = exists(&Identifier {
+            service: item.service,
+            username: item.username,
+            class: item.class,
+        })?;
+        match (item.mode, exists) {
+            (StoreMode::CreateNew, true) => return Err(ErrorKind::Duplicate.into()),
+            (StoreMode::Replace, false) => return Err(ErrorKind::NotFound.into()),
+            _ => {}
+        }
+    }
+
     let (_target, target) = target(item.service, item.username);
     let (_user_name, user_name) = if let Some(username) = item.username {
         w(username)
     } else {
         (Vec::new(), PWSTR::null())
     };
+    // The Credential Manager has no label, so only the comment is stored.
+    let (_comment, comment) = if let Some(comment) = item.comment {
+        w(comment)
+    } else {
+        (Vec::new(), PWSTR::null())
+    };
+    let attributes = item.attributes.iter().copied().collect::<BTreeMap<_, _>>();
+    let (_keywords, mut attributes) = credential_attributes(attributes);
 
     let credentials = CREDENTIALW {
         Flags: CRED_FLAGS(0x0),
         Type: ty(item.class),
         TargetName: target,
-        Comment: PWSTR::null(),
+        Comment: comment,
         LastWritten: FILETIME {
             dwLowDateTime: 0,
             dwHighDateTime: 0,
@@ -36,82 +67,103 @@ pub fn store(item: KeychainItemBuilder) -> Result<()> {
         CredentialBlob: item.secret.as_ptr() as *mut _,
         // TODO: Option to only persist for session. Could be an interesting feature.
         Persist: CRED_PERSIST(0x2),
-        // AttributeCount: attributes.len() as u32,
-        // Attributes: attributes.as_mut_ptr(),
-        AttributeCount: 0,
-        Attributes: ptr::null_mut(),
+        AttributeCount: attributes.len() as u32,
+        Attributes: attributes_ptr(&mut attributes),
         TargetAlias: PWSTR::null(),
         UserName: user_name,
     };
 
-    unsafe { CredWriteW(&credentials as *const _, 0) }.map_err(Error)
+    unsafe { CredWriteW(&credentials as *const _, 0) }.map_err(Error::from)
 }
 
-pub fn load(identifier: &Identifier) -> Result<Option<String>> {
-    // TODO: Don't propagate notfound error or something.
-    let ptr = load_(identifier)?;
-    let cred = unsafe { &*ptr };
+pub fn load(identifier: &Identifier) -> Result<Option<Secret<Vec<u8>>>> {
+    let cred = match Credential::read(identifier) {
+        Ok(cred) => cred,
+        Err(error) => {
+            return match error.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            }
+        }
+    };
 
     // Copy out the secret so that we can free the struct.
     let sl =
         unsafe { slice::from_raw_parts(cred.CredentialBlob, cred.CredentialBlobSize as usize) };
-    let s = str::from_utf8(sl).ok().map(|s| s.to_owned());
+    Ok(Some(Secret::new(sl.to_vec())))
+}
 
-    unsafe { CredFree(ptr as *const _) };
+pub fn metadata(identifier: &Identifier) -> Result<Option<ItemMetadata>> {
+    let cred = match Credential::read(identifier) {
+        Ok(cred) => cred,
+        Err(error) => {
+            return match error.kind() {
+                ErrorKind::NotFound => Ok(None),
+                _ => Err(error),
+            }
+        }
+    };
 
-    Ok(s)
+    let mut metadata = ItemMetadata::new();
+    metadata.class = identifier.class;
+    // The Credential Manager doesn't record when a credential was created.
+    metadata.modified = system_time(cred.LastWritten);
+    if !cred.Comment.is_null() {
+        // SAFETY: Pointer supplied by Windows must be valid.
+        metadata.comment = unsafe { cred.Comment.to_string() }.ok();
+    }
+    metadata.attributes = read_attributes(&cred);
+    Ok(Some(metadata))
 }
 
-pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
-    let previous_ptr = load_(identifier)?;
-    let previous_cred = unsafe { &*previous_ptr };
+pub fn update(identifier: &Identifier, options: &UpdateOptions) -> Result<()> {
+    let previous = Credential::read(identifier)?;
 
-    // TODO: Explain
-    let f = |service, username| {
-        delete(identifier)?;
-        Ok(target(service, username))
+    // The target name includes the username, so a credential is moved by
+    // writing it under its new target name and deleting the old one.
+    let new_identifier = Identifier {
+        service: options.service.unwrap_or(identifier.service),
+        username: options.username.or(identifier.username),
+        class: options.class.unwrap_or(identifier.class),
     };
+    let moved = new_identifier.service != identifier.service
+        || new_identifier.username != identifier.username
+        || ty(new_identifier.class) != ty(identifier.class);
+    if moved && exists(&new_identifier)? {
+        return Err(ErrorKind::Duplicate.into());
+    }
 
-    // TODO: Unwraps
-    let (_target, target) = match (options.service, options.username) {
-        (Some(service), username) => f(service, username)?,
-        (None, Some(username)) => {
-            // TODO: Could also just use the service stored in the identifier.
-
-            // SAFETY: Pointer supplied by Windows must be valid.
-            let previous_target_name = unsafe { previous_cred.TargetName.to_string() }.unwrap();
-            // SAFETY: Pointer supplied by Windows must be valid.
-            let previous_username = unsafe { previous_cred.UserName.to_string() }.unwrap();
-            let service = previous_target_name
-                .strip_prefix(&previous_username)
-                .unwrap()
-                .strip_prefix(TARGET_NAME_SEPARATOR)
-                .unwrap();
-            f(service, Some(username))?
-        }
-        (None, None) => (Vec::new(), previous_cred.TargetName),
+    let (_target, target) = target(new_identifier.service, new_identifier.username);
+    let (_user_name, user_name) = if let Some(username) = new_identifier.username {
+        w(username)
+    } else {
+        (Vec::new(), PWSTR::null())
     };
 
     let (secret_len, secret_ptr) = if let Some(secret) = options.secret {
         (secret.len() as u32, secret.as_ptr() as *mut _)
     } else {
-        (
-            previous_cred.CredentialBlobSize,
-            previous_cred.CredentialBlob,
-        )
+        (previous.CredentialBlobSize, previous.CredentialBlob)
     };
 
-    let (_user_name, user_name) = if let Some(username) = options.username {
-        w(username)
+    let (_comment, comment) = if let Some(comment) = options.comment {
+        w(comment)
     } else {
-        (Vec::new(), previous_cred.UserName)
+        (Vec::new(), previous.Comment)
     };
 
+    let mut attributes = read_attributes(&previous);
+    for &(key, value) in &options.attributes {
+        attributes.insert(key.to_owned(), value.to_owned());
+    }
+    let (_keywords, mut attributes) =
+        credential_attributes(attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
     let credentials = CREDENTIALW {
         Flags: CRED_FLAGS(0x0),
-        Type: options.class.map(ty).unwrap_or(previous_cred.Type),
+        Type: ty(new_identifier.class),
         TargetName: target,
-        Comment: PWSTR::null(),
+        Comment: comment,
         LastWritten: FILETIME {
             dwLowDateTime: 0,
             dwHighDateTime: 0,
@@ -119,39 +171,140 @@ pub fn update(identifier: &Identifier, options: UpdateOptions) -> Result<()> {
         CredentialBlobSize: secret_len,
         CredentialBlob: secret_ptr,
         Persist: CRED_PERSIST(0x2),
-        AttributeCount: 0,
-        Attributes: ptr::null_mut(),
+        AttributeCount: attributes.len() as u32,
+        Attributes: attributes_ptr(&mut attributes),
         TargetAlias: PWSTR::null(),
         UserName: user_name,
     };
 
-    unsafe { CredWriteW(&credentials as *const _, 0) }.map_err(Error)?;
-    unsafe { CredFree(previous_ptr as *const _) };
-
+    unsafe { CredWriteW(&credentials as *const _, 0) }.map_err(Error::from)?;
+    // The old credential is only deleted once the new one is written, so
+    // that the item is never lost.
+    if moved {
+        delete(identifier)?;
+    }
     Ok(())
 }
 
 pub fn delete(identifier: &Identifier) -> Result<()> {
     let (_target, target) = target(identifier.service, identifier.username);
-    unsafe { CredDeleteW(target, ty(identifier.class), 0) }.map_err(Error)
+    unsafe { CredDeleteW(target, ty(identifier.class), 0) }.map_err(Error::from)
 }
 
-fn load_(identifier: &Identifier) -> Result<*const CREDENTIALW> {
-    let (_target, target) = target(identifier.service, identifier.username);
-    let ty = ty(identifier.class);
+pub fn search(options: &SearchOptions) -> Result<Vec<OwnedIdentifier>> {
+    let mut count = 0;
     let mut ptr = ptr::null_mut();
+    if let Err(error) = unsafe {
+        CredEnumerateW(
+            PCWSTR::null(),
+            CRED_ENUMERATE_FLAGS(0x0),
+            &mut count as *mut _,
+            &mut ptr as *mut _,
+        )
+    } {
+        let error = Error::from(error);
+        return match error.kind() {
+            ErrorKind::NotFound => Ok(Vec::new()),
+            _ => Err(error),
+        };
+    }
+
+    // SAFETY: Windows returns an array of `count` valid credential pointers.
+    let credentials = unsafe { slice::from_raw_parts(ptr, count as usize) };
+    let identifiers = credentials
+        .iter()
+        .filter_map(|&cred| {
+            let cred = unsafe { &*cred };
+            if cred.Type != ty(Class::Generic) {
+                return None;
+            }
+            if !options.attributes.is_empty() {
+                let attributes = read_attributes(cred);
+                if !options
+                    .attributes
+                    .iter()
+                    .all(|&(key, value)| attributes.get(key).map(AsRef::as_ref) == Some(value))
+                {
+                    return None;
+                }
+            }
 
-    // TODO: Return option if this is a notfound error.
-    unsafe { CredReadW(target, ty, 0, &mut ptr as *mut _) }.map_err(Error)?;
+            // SAFETY: Pointers supplied by Windows must be valid.
+            let target_name = unsafe { cred.TargetName.to_string() }.ok()?;
+            let username = if cred.UserName.is_null() {
+                None
+            } else {
+                Some(unsafe { cred.UserName.to_string() }.ok()?)
+            };
+            // Credentials stored by other applications may not follow our
+            // target name format, in which case the prefix won't match.
+            let service = match username {
+                Some(ref username) => target_name
+                    .strip_prefix(username.as_str())?
+                    .strip_prefix(TARGET_NAME_SEPARATOR)?
+                    .to_owned(),
+                None => target_name,
+            };
 
-    Ok(ptr)
+            Some(OwnedIdentifier {
+                service,
+                username,
+                class: options.class.unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    unsafe { CredFree(ptr as *const _) };
+
+    Ok(identifiers)
+}
+
+/// A credential read with `CredReadW`, which is freed when dropped.
+struct Credential(*mut CREDENTIALW);
+
+impl Credential {
+    fn read(identifier: &Identifier) -> Result<Self> {
+        let (_target, target) = target(identifier.service, identifier.username);
+        let ty = ty(identifier.class);
+        let mut ptr = ptr::null_mut();
+
+        unsafe { CredReadW(target, ty, 0, &mut ptr as *mut _) }.map_err(Error::from)?;
+
+        Ok(Self(ptr))
+    }
+}
+
+impl Deref for Credential {
+    type Target = CREDENTIALW;
+
+    fn deref(&self) -> &CREDENTIALW {
+        // SAFETY: `CredReadW` returns a valid credential, which lives until
+        // it is freed on drop.
+        unsafe { &*self.0 }
+    }
+}
+
+impl Drop for Credential {
+    fn drop(&mut self) {
+        unsafe { CredFree(self.0 as *const _) };
+    }
+}
+
+fn exists(identifier: &Identifier) -> Result<bool> {
+    match Credential::read(identifier) {
+        Ok(_) => Ok(true),
+        Err(error) => match error.kind() {
+            ErrorKind::NotFound => Ok(false),
+            _ => Err(error),
+        },
+    }
 }
 
 fn target(service: &str, username: Option<&str>) -> (Vec<u16>, PWSTR) {
     if let Some(username) = username {
         // TODO: Do we bother documenting this. It's kind of an internal implementation
-        // detail. Note that the username and service can contain \x1f, because when
-        // updating we strip the username rather than splitting on \x1f.
+        // detail. Note that the username and service can contain \x1f, because
+        // searching strips the username rather than splitting on \x1f.
         //
         // TODO: Would we rather use a printable character?
         w(&format!("{}{TARGET_NAME_SEPARATOR}{}", username, service))
@@ -160,6 +313,67 @@ fn target(service: &str, username: Option<&str>) -> (Vec<u16>, PWSTR) {
     }
 }
 
+// The returned keywords must not be dropped until the attributes are no longer
+// in use. The values borrow from `attributes`.
+fn credential_attributes<'a>(
+    attributes: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> (Vec<Vec<u16>>, Vec<CREDENTIAL_ATTRIBUTEW>) {
+    attributes
+        .into_iter()
+        .map(|(key, value)| {
+            let (keyword, keyword_ptr) = w(key);
+            let attribute = CREDENTIAL_ATTRIBUTEW {
+                Keyword: keyword_ptr,
+                Flags: 0,
+                ValueSize: value.len() as u32,
+                Value: value.as_ptr() as *mut _,
+            };
+            (keyword, attribute)
+        })
+        .unzip()
+}
+
+fn attributes_ptr(attributes: &mut [CREDENTIAL_ATTRIBUTEW]) -> *mut CREDENTIAL_ATTRIBUTEW {
+    if attributes.is_empty() {
+        ptr::null_mut()
+    } else {
+        attributes.as_mut_ptr()
+    }
+}
+
+fn read_attributes(cred: &CREDENTIALW) -> BTreeMap<String, String> {
+    if cred.Attributes.is_null() {
+        return BTreeMap::new();
+    }
+
+    // SAFETY: Windows returns an array of `AttributeCount` valid attributes.
+    let attributes =
+        unsafe { slice::from_raw_parts(cred.Attributes, cred.AttributeCount as usize) };
+    attributes
+        .iter()
+        .filter_map(|attribute| {
+            // SAFETY: Pointer supplied by Windows must be valid.
+            let key = unsafe { attribute.Keyword.to_string() }.ok()?;
+            let value = if attribute.Value.is_null() {
+                Vec::new()
+            } else {
+                unsafe { slice::from_raw_parts(attribute.Value, attribute.ValueSize as usize) }
+                    .to_vec()
+            };
+            Some((key, String::from_utf8(value).ok()?))
+        })
+        .collect()
+}
+
+fn system_time(time: FILETIME) -> Option<SystemTime> {
+    // The number of 100ns intervals between 1601-01-01 and 1970-01-01.
+    const UNIX_EPOCH_INTERVALS: u64 = 116_444_736_000_000_000;
+
+    let intervals = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+    let since_epoch = intervals.checked_sub(UNIX_EPOCH_INTERVALS)?;
+    UNIX_EPOCH.checked_add(Duration::from_nanos(since_epoch.checked_mul(100)?))
+}
+
 fn ty(class: Class) -> CRED_TYPE {
     CRED_TYPE(match class {
         // CRED_TYPE_GENERIC_CERTIFICATE
@@ -177,3 +391,30 @@ fn w(s: &str) -> (Vec<u16>, PWSTR) {
     let pwstr = PWSTR(utf16.as_mut_ptr());
     (utf16, pwstr)
 }
+
+impl From<windows_result::Error> for Error {
+    fn from(error: windows_result::Error) -> Self {
+        const ERROR_ACCESS_DENIED: HRESULT = HRESULT::from_win32(5);
+        const ERROR_NOT_SUPPORTED: HRESULT = HRESULT::from_win32(50);
+        const ERROR_INVALID_PARAMETER: HRESULT = HRESULT::from_win32(87);
+        const ERROR_ALREADY_EXISTS: HRESULT = HRESULT::from_win32(183);
+        const ERROR_NOT_FOUND: HRESULT = HRESULT::from_win32(1168);
+        const ERROR_CANCELLED: HRESULT = HRESULT::from_win32(1223);
+        const ERROR_NO_SUCH_LOGON_SESSION: HRESULT = HRESULT::from_win32(1312);
+        const ERROR_TIMEOUT: HRESULT = HRESULT::from_win32(1460);
+        const ERROR_BAD_USERNAME: HRESULT = HRESULT::from_win32(2202);
+
+        let kind = match error.code() {
+            ERROR_NOT_FOUND => ErrorKind::NotFound,
+            ERROR_ALREADY_EXISTS => ErrorKind::Duplicate,
+            ERROR_ACCESS_DENIED => ErrorKind::AccessDenied,
+            ERROR_CANCELLED => ErrorKind::Cancelled,
+            ERROR_TIMEOUT => ErrorKind::TimedOut,
+            ERROR_NO_SUCH_LOGON_SESSION => ErrorKind::Unavailable,
+            ERROR_INVALID_PARAMETER | ERROR_BAD_USERNAME => ErrorKind::InvalidData,
+            ERROR_NOT_SUPPORTED => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        };
+        Error::new(kind, error)
+    }
+}
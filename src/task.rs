@@ -0,0 +1,228 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::{CancellationToken, Error, ErrorKind, Result};
+
+/// A future resolving to the result of an asynchronous keychain operation.
+///
+/// The operation runs on a worker thread, so the future can be awaited on any
+/// executor. Dropping the task before it completes cancels the operation; see
+/// [`CancellationToken`].
+///
+/// If the [`Keychain`](crate::Keychain) has a timeout, the operation is
+/// cancelled once it expires and the task resolves with an
+/// [`ErrorKind::TimedOut`] error.
+#[must_use = "tasks are cancelled when dropped"]
+pub struct Task<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    token: CancellationToken,
+}
+
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+    timed_out: bool,
+}
+
+impl<T> Task<T>
+where
+    T: Send + 'static,
+{
+    /// Runs `f` on a worker thread, cancelling it if it doesn't complete
+    /// within `timeout`.
+    pub(crate) fn spawn<F>(f: F, timeout: Option<Duration>) -> Self
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+            timed_out: false,
+        }));
+        let token = CancellationToken::new();
+
+        let weak = Arc::downgrade(&shared);
+        token.on_cancel(move || {
+            if let Some(shared) = weak.upgrade() {
+                wake(&shared);
+            }
+        });
+
+        let thread_shared = shared.clone();
+        let thread_token = token.clone();
+        let spawned = thread::Builder::new()
+            .name("robius-keychain".to_owned())
+            .spawn(move || {
+                let result = if thread_token.is_cancelled() {
+                    Err(ErrorKind::Cancelled.into())
+                } else {
+                    thread_token.scope(f)
+                };
+                complete(&thread_shared, result);
+            });
+        if let Err(error) = spawned {
+            complete(&shared, Err(Error::from(error)));
+            return Self { shared, token };
+        }
+
+        // A timeout too long to represent never expires.
+        if let Some(deadline) = timeout.and_then(|timeout| Instant::now().checked_add(timeout)) {
+            let timer_shared = Arc::downgrade(&shared);
+            let timer_token = token.clone();
+            let on_timeout = Box::new(move || {
+                // A dropped task has been cancelled already.
+                let Some(shared) = timer_shared.upgrade() else {
+                    return;
+                };
+                let mut shared = shared.lock().unwrap_or_else(PoisonError::into_inner);
+                if shared.result.is_none() && !timer_token.is_cancelled() {
+                    shared.timed_out = true;
+                    drop(shared);
+                    timer_token.cancel();
+                }
+            });
+            if let Err(error) = schedule(deadline, on_timeout) {
+                token.cancel();
+                complete(&shared, Err(error));
+            }
+        }
+
+        Self { shared, token }
+    }
+
+    /// Blocks the current thread until the task completes.
+    pub(crate) fn wait(mut self) -> Result<T> {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(result) = Pin::new(&mut self).poll(&mut cx) {
+                return result;
+            }
+            thread::park();
+        }
+    }
+}
+
+impl<T> Task<T> {
+    /// Returns a token that cancels the operation.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+        // Checked first, as the operation may have failed with a cancellation
+        // error after the timeout.
+        if shared.timed_out {
+            return Poll::Ready(Err(ErrorKind::TimedOut.into()));
+        }
+        if let Some(result) = shared.result.take() {
+            return Poll::Ready(result);
+        }
+        if self.token.is_cancelled() {
+            return Poll::Ready(Err(ErrorKind::Cancelled.into()));
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+impl<T> fmt::Debug for Task<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Task")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// A callback run by the timer thread once its deadline has passed.
+type Timeout = (Instant, Box<dyn FnOnce() + Send>);
+
+/// Runs `on_timeout` at `deadline` on a timer thread shared by all tasks.
+fn schedule(deadline: Instant, on_timeout: Box<dyn FnOnce() + Send>) -> Result<()> {
+    static TIMER: OnceLock<Option<Sender<Timeout>>> = OnceLock::new();
+
+    let timer = TIMER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("robius-keychain-timer".to_owned())
+            .spawn(move || run_timer(receiver))
+            .ok()?;
+        Some(sender)
+    });
+    timer
+        .as_ref()
+        .and_then(|timer| timer.send((deadline, on_timeout)).ok())
+        .ok_or_else(|| Error::new(ErrorKind::Other, "failed to start the timeout thread"))
+}
+
+fn run_timer(receiver: mpsc::Receiver<Timeout>) {
+    let mut pending: Vec<Timeout> = Vec::new();
+    loop {
+        let received = match pending.iter().map(|(deadline, _)| *deadline).min() {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(timeout) => pending.push(timeout),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let (expired, rest) = pending
+            .into_iter()
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        pending = rest;
+        for (_, on_timeout) in expired {
+            on_timeout();
+        }
+    }
+}
+
+fn complete<T>(shared: &Mutex<Shared<T>>, result: Result<T>) {
+    shared.lock().unwrap_or_else(PoisonError::into_inner).result = Some(result);
+    wake(shared);
+}
+
+fn wake<T>(shared: &Mutex<Shared<T>>) {
+    // The lock is released before waking, as the waker may poll the task.
+    let waker = shared
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .waker
+        .take();
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}